@@ -0,0 +1,624 @@
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use syn::meta::ParseNestedMeta;
+use syn::spanned::Spanned;
+use syn::{Data, DeriveInput, Error, Expr, Fields, Ident, LitBool, LitStr, Result, Type};
+
+/// Derives `ofx::OfxParams` for a struct of parameter values.
+///
+/// Every field becomes a parameter, named after the field in lowerCamelCase
+/// unless overridden. The derive generates the definition code for
+/// DescribeInContext, a `<Name>Handles` struct of `ParamHandle`s for the
+/// instance, and `values_at(time)` on it, returning the annotated struct.
+///
+/// Field attributes, all optional:
+/// `name`, `label`, `hint`, `script_name`, `default`, `range = (min, max)`,
+/// `display_range = (min, max)`, `double_type = Scale`, `string_type = FilePath`,
+/// `parent = "group"`, `page = "page"`. Enum fields implementing `ChoiceEnum`
+/// need `choice`, or `str_choice` to store the option names.
+/// `PathBuf` fields are file path strings unless `string_type = DirectoryPath`.
+///
+/// Struct attributes declare groups and page labels:
+/// `#[ofx(group(name = "...", label = "...", hint = "...", parent = "...", page = "...", open = true))]`
+/// `#[ofx(page(name = "...", label = "..."))]`
+#[proc_macro_derive(OfxParams, attributes(ofx))]
+pub fn derive_ofx_params(input: TokenStream) -> TokenStream {
+	let input = syn::parse_macro_input!(input as DeriveInput);
+	match expand(&input) {
+		Ok(tokens) => tokens.into(),
+		Err(error) => error.to_compile_error().into(),
+	}
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+	Double,
+	Int,
+	Boolean,
+	String,
+	Path,
+	Choice,
+	StrChoice,
+}
+
+struct ParamField {
+	ident: Ident,
+	ty: Type,
+	kind: Kind,
+	name: String,
+	label: Option<LitStr>,
+	hint: Option<LitStr>,
+	script_name: Option<LitStr>,
+	default: Option<Expr>,
+	range: Option<(Expr, Expr)>,
+	display_range: Option<(Expr, Expr)>,
+	double_type: Option<Ident>,
+	string_type: Option<Ident>,
+	parent: Option<LitStr>,
+	page: Option<String>,
+}
+
+struct Group {
+	name: String,
+	label: Option<LitStr>,
+	hint: Option<LitStr>,
+	parent: Option<LitStr>,
+	page: Option<String>,
+	open: Option<LitBool>,
+}
+
+struct Page {
+	name: String,
+	label: Option<LitStr>,
+	children: Vec<String>,
+}
+
+fn expand(input: &DeriveInput) -> Result<TokenStream2> {
+	if !input.generics.params.is_empty() {
+		return Err(Error::new(
+			input.generics.span(),
+			"OfxParams cannot be derived for generic structs",
+		));
+	}
+
+	let fields = match input.data {
+		Data::Struct(ref data) => match data.fields {
+			Fields::Named(ref fields) => &fields.named,
+			_ => {
+				return Err(Error::new(
+					input.ident.span(),
+					"OfxParams requires a struct with named fields",
+				))
+			}
+		},
+		_ => {
+			return Err(Error::new(
+				input.ident.span(),
+				"OfxParams can only be derived for structs",
+			))
+		}
+	};
+
+	let mut groups = Vec::new();
+	let mut pages = Vec::new();
+	for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("ofx")) {
+		attr.parse_nested_meta(|meta| {
+			if meta.path.is_ident("group") {
+				groups.push(parse_group(&meta)?);
+				Ok(())
+			} else if meta.path.is_ident("page") {
+				let (name, label) = parse_page(&meta)?;
+				pages.push(Page {
+					name,
+					label,
+					children: Vec::new(),
+				});
+				Ok(())
+			} else {
+				Err(meta.error("expected `group(...)` or `page(...)`"))
+			}
+		})?;
+	}
+
+	let params = fields
+		.iter()
+		.map(parse_field)
+		.collect::<Result<Vec<_>>>()?;
+
+	// groups go on their page just before the first of their children
+	let mut placed_groups = Vec::new();
+	for param in &params {
+		if let (Some(parent), Some(page)) = (param.parent.as_ref(), param.page.as_ref()) {
+			let parent = parent.value();
+			if let Some(group) = groups.iter().find(|group| group.name == parent) {
+				if group.page.as_ref() == Some(page) && !placed_groups.contains(&parent) {
+					page_entry(&mut pages, page).children.push(parent.clone());
+					placed_groups.push(parent);
+				}
+			}
+		}
+		if let Some(ref page) = param.page {
+			page_entry(&mut pages, page)
+				.children
+				.push(param.name.clone());
+		}
+	}
+	for group in &groups {
+		if let Some(ref page) = group.page {
+			if !placed_groups.contains(&group.name) {
+				page_entry(&mut pages, page)
+					.children
+					.push(group.name.clone());
+			}
+		}
+	}
+
+	let group_definitions = groups.iter().map(define_group);
+	let param_definitions = params.iter().map(define_param);
+	let page_definitions = pages.iter().map(define_page);
+
+	let name = &input.ident;
+	let visibility = &input.vis;
+	let handles_name = format_ident!("{}Handles", name);
+	let handles_doc = format!("Instance-side parameter handles of [`{}`]", name);
+	let idents: Vec<_> = params.iter().map(|param| &param.ident).collect();
+	let types: Vec<_> = params.iter().map(|param| &param.ty).collect();
+	let names: Vec<_> = params.iter().map(|param| &param.name).collect();
+	let getters: Vec<_> = params
+		.iter()
+		.map(|param| match param.kind {
+			Kind::Choice | Kind::StrChoice => quote!(get_choice_at_time),
+			_ => quote!(get_value_at_time),
+		})
+		.collect();
+
+	Ok(quote! {
+		#[doc = #handles_doc]
+		#[derive(Clone)]
+		#visibility struct #handles_name {
+			#(pub #idents: ::ofx::ParamHandle<#types>,)*
+		}
+
+		impl #handles_name {
+			pub fn values_at(&self, time: ::ofx::Time) -> ::ofx::Result<#name> {
+				Ok(#name {
+					#(#idents: self.#idents.#getters(time)?,)*
+				})
+			}
+		}
+
+		impl ::ofx::OfxParams for #name {
+			type Handles = #handles_name;
+
+			fn define(param_set: &mut ::ofx::ParamSetHandle) -> ::ofx::Result<()> {
+				#(#group_definitions)*
+				#(#param_definitions)*
+				#(#page_definitions)*
+				Ok(())
+			}
+
+			fn fetch(param_set: &::ofx::ParamSetHandle) -> ::ofx::Result<#handles_name> {
+				Ok(#handles_name {
+					#(#idents: param_set.parameter(#names)?,)*
+				})
+			}
+		}
+	})
+}
+
+fn page_entry<'a>(pages: &'a mut Vec<Page>, name: &str) -> &'a mut Page {
+	if let Some(index) = pages.iter().position(|page| page.name == name) {
+		&mut pages[index]
+	} else {
+		pages.push(Page {
+			name: name.to_owned(),
+			label: None,
+			children: Vec::new(),
+		});
+		pages.last_mut().unwrap()
+	}
+}
+
+fn kind_of(ty: &Type, choice: Option<Kind>) -> Result<Kind> {
+	let type_name = match *ty {
+		Type::Path(ref path) => path
+			.path
+			.segments
+			.last()
+			.map(|segment| segment.ident.to_string())
+			.unwrap_or_default(),
+		_ => String::new(),
+	};
+	let kind = match type_name.as_str() {
+		"Double" | "f64" => Kind::Double,
+		"Int" | "i32" => Kind::Int,
+		"Bool" | "bool" => Kind::Boolean,
+		"String" => Kind::String,
+		"PathBuf" => Kind::Path,
+		_ => return choice.ok_or_else(|| Error::new_spanned(ty, "unsupported parameter type")),
+	};
+	match choice {
+		Some(_) => Err(Error::new_spanned(
+			ty,
+			"`choice` only applies to ChoiceEnum fields",
+		)),
+		None => Ok(kind),
+	}
+}
+
+fn to_lower_camel_case(name: &str) -> String {
+	let mut result = String::with_capacity(name.len());
+	let mut upper_next = false;
+	for c in name.trim_start_matches('_').chars() {
+		if c == '_' {
+			upper_next = true;
+		} else if upper_next {
+			result.extend(c.to_uppercase());
+			upper_next = false;
+		} else {
+			result.push(c);
+		}
+	}
+	result
+}
+
+fn parse_pair(meta: &ParseNestedMeta) -> Result<(Expr, Expr)> {
+	match meta.value()?.parse::<Expr>()? {
+		Expr::Tuple(ref tuple) if tuple.elems.len() == 2 => {
+			Ok((tuple.elems[0].clone(), tuple.elems[1].clone()))
+		}
+		other => Err(Error::new(other.span(), "expected `(min, max)`")),
+	}
+}
+
+fn parse_field(field: &syn::Field) -> Result<ParamField> {
+	let ident = field.ident.clone().unwrap();
+	let mut param = ParamField {
+		name: to_lower_camel_case(&ident.to_string()),
+		ident,
+		ty: field.ty.clone(),
+		kind: Kind::Choice,
+		label: None,
+		hint: None,
+		script_name: None,
+		default: None,
+		range: None,
+		display_range: None,
+		double_type: None,
+		string_type: None,
+		parent: None,
+		page: None,
+	};
+	let mut choice = None;
+
+	for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("ofx")) {
+		attr.parse_nested_meta(|meta| {
+			if meta.path.is_ident("name") {
+				param.name = meta.value()?.parse::<LitStr>()?.value();
+			} else if meta.path.is_ident("label") {
+				param.label = Some(meta.value()?.parse()?);
+			} else if meta.path.is_ident("hint") {
+				param.hint = Some(meta.value()?.parse()?);
+			} else if meta.path.is_ident("script_name") {
+				param.script_name = Some(meta.value()?.parse()?);
+			} else if meta.path.is_ident("default") {
+				param.default = Some(meta.value()?.parse()?);
+			} else if meta.path.is_ident("range") {
+				param.range = Some(parse_pair(&meta)?);
+			} else if meta.path.is_ident("display_range") {
+				param.display_range = Some(parse_pair(&meta)?);
+			} else if meta.path.is_ident("double_type") {
+				param.double_type = Some(meta.value()?.parse()?);
+			} else if meta.path.is_ident("string_type") {
+				param.string_type = Some(meta.value()?.parse()?);
+			} else if meta.path.is_ident("parent") {
+				param.parent = Some(meta.value()?.parse()?);
+			} else if meta.path.is_ident("page") {
+				param.page = Some(meta.value()?.parse::<LitStr>()?.value());
+			} else if meta.path.is_ident("choice") {
+				choice = Some(Kind::Choice);
+			} else if meta.path.is_ident("str_choice") {
+				choice = Some(Kind::StrChoice);
+			} else {
+				return Err(meta.error("unknown parameter attribute"));
+			}
+			Ok(())
+		})?;
+	}
+
+	param.kind = kind_of(&param.ty, choice)?;
+	let span = param.ident.span();
+	if param.double_type.is_some() && param.kind != Kind::Double {
+		return Err(Error::new(span, "`double_type` only applies to Double fields"));
+	}
+	if param.string_type.is_some() && param.kind != Kind::String && param.kind != Kind::Path {
+		return Err(Error::new(span, "`string_type` only applies to String and PathBuf fields"));
+	}
+	if (param.range.is_some() || param.display_range.is_some())
+		&& param.kind != Kind::Double
+		&& param.kind != Kind::Int
+	{
+		return Err(Error::new(span, "ranges only apply to Double and Int fields"));
+	}
+	Ok(param)
+}
+
+fn parse_group(meta: &ParseNestedMeta) -> Result<Group> {
+	let mut name = None;
+	let mut group = Group {
+		name: String::new(),
+		label: None,
+		hint: None,
+		parent: None,
+		page: None,
+		open: None,
+	};
+	meta.parse_nested_meta(|meta| {
+		if meta.path.is_ident("name") {
+			name = Some(meta.value()?.parse::<LitStr>()?.value());
+		} else if meta.path.is_ident("label") {
+			group.label = Some(meta.value()?.parse()?);
+		} else if meta.path.is_ident("hint") {
+			group.hint = Some(meta.value()?.parse()?);
+		} else if meta.path.is_ident("parent") {
+			group.parent = Some(meta.value()?.parse()?);
+		} else if meta.path.is_ident("page") {
+			group.page = Some(meta.value()?.parse::<LitStr>()?.value());
+		} else if meta.path.is_ident("open") {
+			group.open = Some(meta.value()?.parse()?);
+		} else {
+			return Err(meta.error("unknown group attribute"));
+		}
+		Ok(())
+	})?;
+	group.name = name.ok_or_else(|| meta.error("group requires a `name`"))?;
+	Ok(group)
+}
+
+fn parse_page(meta: &ParseNestedMeta) -> Result<(String, Option<LitStr>)> {
+	let mut name = None;
+	let mut label = None;
+	meta.parse_nested_meta(|meta| {
+		if meta.path.is_ident("name") {
+			name = Some(meta.value()?.parse::<LitStr>()?.value());
+		} else if meta.path.is_ident("label") {
+			label = Some(meta.value()?.parse()?);
+		} else {
+			return Err(meta.error("unknown page attribute"));
+		}
+		Ok(())
+	})?;
+	let name = name.ok_or_else(|| meta.error("page requires a `name`"))?;
+	Ok((name, label))
+}
+
+fn define_common(
+	label: &Option<LitStr>,
+	hint: &Option<LitStr>,
+	parent: &Option<LitStr>,
+) -> TokenStream2 {
+	let label = label
+		.as_ref()
+		.map(|label| quote!(::ofx::Label::CanSet::set_label(&mut param, #label)?;));
+	let hint = hint
+		.as_ref()
+		.map(|hint| quote!(::ofx::Hint::CanSet::set_hint(&mut param, #hint)?;));
+	let parent = parent
+		.as_ref()
+		.map(|parent| quote!(::ofx::Parent::CanSet::set_parent(&mut param, #parent)?;));
+	quote!(#label #hint #parent)
+}
+
+fn define_group(group: &Group) -> TokenStream2 {
+	let name = &group.name;
+	let common = define_common(&group.label, &group.hint, &group.parent);
+	let open = group
+		.open
+		.as_ref()
+		.map(|open| quote!(::ofx::GroupOpen::CanSet::set_group_open(&mut param, #open)?;));
+	quote! {
+		{
+			let mut param = param_set.param_define_group(#name)?;
+			#common
+			#open
+		}
+	}
+}
+
+fn define_param(param: &ParamField) -> TokenStream2 {
+	let name = &param.name;
+	let ty = &param.ty;
+	let common = define_common(&param.label, &param.hint, &param.parent);
+	let script_name = param
+		.script_name
+		.as_ref()
+		.map(|script_name| quote!(#script_name))
+		.unwrap_or_else(|| quote!(#name));
+
+	let (define, specific) = match param.kind {
+		Kind::Double | Kind::Int => {
+			let (define, setters) = if param.kind == Kind::Double {
+				(
+					quote!(param_define_double),
+					quote!(::ofx::DoubleParams::CanSet),
+				)
+			} else {
+				(quote!(param_define_int), quote!(::ofx::IntParams::CanSet))
+			};
+			let double_type = param.double_type.as_ref().map(|double_type| {
+				quote!(#setters::set_double_type(&mut param, ::ofx::ParamDoubleType::#double_type)?;)
+			});
+			let default = param
+				.default
+				.as_ref()
+				.map(|default| quote!(#setters::set_default(&mut param, #default)?;));
+			let range = param.range.as_ref().map(|(min, max)| {
+				quote! {
+					#setters::set_min(&mut param, #min)?;
+					#setters::set_max(&mut param, #max)?;
+				}
+			});
+			let display_range = param.display_range.as_ref().map(|(min, max)| {
+				quote! {
+					#setters::set_display_min(&mut param, #min)?;
+					#setters::set_display_max(&mut param, #max)?;
+				}
+			});
+			(define, quote!(#double_type #default #range #display_range))
+		}
+		Kind::Boolean => (
+			quote!(param_define_boolean),
+			param
+				.default
+				.as_ref()
+				.map(|default| quote!(::ofx::BooleanParams::CanSet::set_default(&mut param, #default)?;))
+				.unwrap_or_default(),
+		),
+		Kind::String => {
+			let string_type = param.string_type.as_ref().map(|string_type| {
+				quote!(::ofx::StringParams::CanSet::set_string_type(&mut param, ::ofx::ParamStringType::#string_type)?;)
+			});
+			let default = param
+				.default
+				.as_ref()
+				.map(|default| quote!(::ofx::StringParams::CanSet::set_default(&mut param, #default)?;));
+			(quote!(param_define_string), quote!(#string_type #default))
+		}
+		Kind::Path => {
+			let string_type = param
+				.string_type
+				.clone()
+				.unwrap_or_else(|| Ident::new("FilePath", param.ident.span()));
+			let default = param
+				.default
+				.as_ref()
+				.map(|default| quote!(::ofx::StringParams::CanSet::set_default(&mut param, #default)?;));
+			(
+				quote!(param_define_string),
+				quote! {
+					::ofx::StringParams::CanSet::set_string_type(&mut param, ::ofx::ParamStringType::#string_type)?;
+					#default
+				},
+			)
+		}
+		Kind::Choice | Kind::StrChoice => {
+			let (define, setters) = if param.kind == Kind::Choice {
+				(
+					quote!(param_define_choice),
+					quote!(::ofx::ChoiceParams::CanSet),
+				)
+			} else {
+				(
+					quote!(param_define_str_choice),
+					quote!(::ofx::StrChoiceParams::CanSet),
+				)
+			};
+			let default = param
+				.default
+				.as_ref()
+				.map(|default| quote!(#setters::set_default_choice::<#ty>(&mut param, #default)?;));
+			(
+				define,
+				quote! {
+					#setters::set_choice_enum::<#ty>(&mut param)?;
+					#default
+				},
+			)
+		}
+	};
+
+	quote! {
+		{
+			let mut param = param_set.#define(#name)?;
+			::ofx::ScriptName::CanSet::set_script_name(&mut param, #script_name)?;
+			#common
+			#specific
+		}
+	}
+}
+
+fn define_page(page: &Page) -> TokenStream2 {
+	let name = &page.name;
+	let children = &page.children;
+	let label = page
+		.label
+		.as_ref()
+		.map(|label| quote!(::ofx::Label::CanSet::set_label(&mut param, #label)?;));
+	quote! {
+		{
+			let mut param = param_set.param_define_page(#name)?;
+			#label
+			::ofx::Children::CanSet::set_children(&mut param, &[#(#children),*])?;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn expand_str(input: &str) -> Result<String> {
+		expand(&syn::parse_str(input)?).map(|tokens| tokens.to_string())
+	}
+
+	// token streams print with spaces between tokens
+	fn contains(expansion: &str, code: &str) -> bool {
+		let strip = |code: &str| code.split_whitespace().collect::<String>();
+		strip(expansion).contains(&strip(code))
+	}
+
+	#[test]
+	fn expands_define_and_fetch() {
+		let expansion = expand_str(
+			r#"
+			struct Params {
+				#[ofx(label = "Gain", default = 1.0, range = (0.0, 4.0))]
+				gain: f64,
+				#[ofx(choice, default = Filter::Bilinear)]
+				filter: Filter,
+				#[ofx(str_choice)]
+				look: Look,
+				lut_file: PathBuf,
+			}
+			"#,
+		)
+		.unwrap();
+		assert!(contains(&expansion, "param_set.param_define_double(\"gain\")?"));
+		assert!(contains(&expansion, "::ofx::DoubleParams::CanSet::set_max(&mut param, 4.0)?"));
+		assert!(contains(&expansion, "param_set.param_define_choice(\"filter\")?"));
+		assert!(contains(
+			&expansion,
+			"::ofx::ChoiceParams::CanSet::set_default_choice::<Filter>(&mut param, Filter::Bilinear)?"
+		));
+		assert!(contains(&expansion, "param_set.param_define_str_choice(\"look\")?"));
+		assert!(contains(
+			&expansion,
+			"::ofx::StringParams::CanSet::set_string_type(&mut param, ::ofx::ParamStringType::FilePath)?"
+		));
+		assert!(contains(&expansion, "lut_file: param_set.parameter(\"lutFile\")?"));
+		assert!(contains(&expansion, "filter: self.filter.get_choice_at_time(time)?"));
+		assert!(contains(&expansion, "gain: self.gain.get_value_at_time(time)?"));
+	}
+
+	#[test]
+	fn rejects_unknown_types() {
+		let error = expand_str("struct Params { colour: RGBAColourD }").unwrap_err();
+		assert_eq!(error.to_string(), "unsupported parameter type");
+		let error = expand_str("struct Params { #[ofx(choice)] gain: f64 }").unwrap_err();
+		assert_eq!(error.to_string(), "`choice` only applies to ChoiceEnum fields");
+	}
+
+	#[test]
+	fn lower_camel_case_names() {
+		assert_eq!(to_lower_camel_case("scale"), "scale");
+		assert_eq!(to_lower_camel_case("scale_r"), "scaleR");
+		assert_eq!(to_lower_camel_case("scale_components"), "scaleComponents");
+		assert_eq!(to_lower_camel_case("_hidden_value"), "hiddenValue");
+	}
+}
@@ -2,6 +2,8 @@ enum eOfxStatus {
 	Unused = -1,
 	OK = kOfxStatOK,
 	ReplyDefault = kOfxStatReplyDefault,
+	ReplyYes = kOfxStatReplyYes,
+	ReplyNo = kOfxStatReplyNo,
 	Failed = kOfxStatFailed,
 	ErrFatal = kOfxStatErrFatal,
 	ErrBadHandle = kOfxStatErrBadHandle,
@@ -31,3 +33,31 @@ enum eOfxStatus {
 #define kOfxImageEffectPropMetalCommandQueue "OfxImageEffectPropMetalCommandQueue"
 
 #define kOfxImageEffectPropResolvePage "OfxImageEffectPropResolvePage"
+
+#ifndef kOfxParamTypeStrChoice
+#define kOfxParamTypeStrChoice "OfxParamTypeStrChoice"
+#endif
+#ifndef kOfxParamPropChoiceOrder
+#define kOfxParamPropChoiceOrder "OfxParamPropChoiceOrder"
+#endif
+#ifndef kOfxParamPropChoiceEnum
+#define kOfxParamPropChoiceEnum "OfxParamPropChoiceEnum"
+#endif
+#ifndef kOfxParamPropChoiceLabelOption
+#define kOfxParamPropChoiceLabelOption "OfxParamPropChoiceLabelOption"
+#endif
+#ifndef kOfxParamHostPropSupportsStrChoice
+#define kOfxParamHostPropSupportsStrChoice "OfxParamHostPropSupportsStrChoice"
+#endif
+#ifndef kOfxParamHostPropSupportsStrChoiceAnimation
+#define kOfxParamHostPropSupportsStrChoiceAnimation "OfxParamHostPropSupportsStrChoiceAnimation"
+#endif
+#ifndef kNatronOfxParamPropChoiceHostCanAddOptions
+#define kNatronOfxParamPropChoiceHostCanAddOptions "NatronOfxParamPropChoiceHostCanAddOptions"
+#endif
+#ifndef kFnOfxParamPropGroupIsTab
+#define kFnOfxParamPropGroupIsTab "FnOfxParamPropGroupIsTab"
+#endif
+#ifndef kOfxParamPropStringFilePathExists
+#define kOfxParamPropStringFilePathExists "OfxParamPropStringFilePathExists"
+#endif
@@ -0,0 +1,317 @@
+use enums::*;
+use handle::*;
+use property::*;
+use result::*;
+use suites::*;
+use types::*;
+
+/// A GPU API a host can render an effect with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpuApi {
+	OpenGL,
+	OpenCL,
+	Cuda,
+	Metal,
+}
+
+/// Which optional suites the host provides
+#[derive(Debug, Clone, Default)]
+pub struct HostSuites {
+	pub message_v2: bool,
+	pub progress_v2: bool,
+	pub parametric_parameter: bool,
+	pub opengl_render: bool,
+}
+
+/// What the host supports, read once when the plugin is loaded and available
+/// from every action through `PluginContext::capabilities`. Properties the
+/// host does not report read as empty or false, limits as unlimited.
+#[derive(Debug, Clone)]
+pub struct HostCapabilities {
+	pub name: String,
+	pub label: String,
+	pub version: Vec<Int>,
+	pub version_label: String,
+	pub suites: HostSuites,
+	pub contexts: Vec<ImageEffectContext>,
+	pub components: Vec<ImageComponent>,
+	pub depths: Vec<BitDepth>,
+	pub gpu_apis: Vec<GpuApi>,
+	pub is_background: bool,
+	pub supports_tiles: bool,
+	pub supports_multi_resolution: bool,
+	pub supports_multiple_clip_depths: bool,
+	pub supports_multiple_clip_pars: bool,
+	pub temporal_clip_access: bool,
+	pub supports_str_choice: bool,
+	/// -1 when unlimited
+	pub max_parameters: Int,
+	/// -1 when unlimited, 0 when the host has no pages
+	pub max_pages: Int,
+	pub page_rows: Int,
+	pub page_columns: Int,
+}
+
+fn all_enums<P, E>(host: &ImageEffectHost) -> Vec<E>
+where
+	P: Named + Get<ReturnType = std::ffi::CString>,
+	E: IdentifiedEnum,
+{
+	host.get_all::<P>()
+		.unwrap_or_default()
+		.iter()
+		.filter_map(|value| E::from_cstring(value))
+		.collect()
+}
+
+// the GPU "render supported" properties are "false", "true" or "needed"
+fn supports_gpu<P>(host: &ImageEffectHost) -> bool
+where
+	P: Named + Get<ReturnType = String>,
+{
+	host.get::<P>().is_ok_and(|value| value != "false")
+}
+
+impl HostCapabilities {
+	pub(crate) fn new(host: &ImageEffectHost, suites: &Suites) -> Self {
+		let suites = HostSuites {
+			message_v2: suites.message_v2().is_some(),
+			progress_v2: suites.progress_v2().is_some(),
+			parametric_parameter: suites.parametric_parameter().is_some(),
+			opengl_render: suites.image_effect_opengl_render().is_some(),
+		};
+		HostCapabilities::read(host, suites)
+	}
+
+	fn read(host: &ImageEffectHost, suites: HostSuites) -> Self {
+		let mut gpu_apis = Vec::new();
+		if suites.opengl_render && supports_gpu::<OpenGLRenderSupported::Property>(host) {
+			gpu_apis.push(GpuApi::OpenGL);
+		}
+		if supports_gpu::<OpenCLRenderSupported::Property>(host) {
+			gpu_apis.push(GpuApi::OpenCL);
+		}
+		if supports_gpu::<CudaRenderSupported::Property>(host) {
+			gpu_apis.push(GpuApi::Cuda);
+		}
+		if supports_gpu::<MetalRenderSupported::Property>(host) {
+			gpu_apis.push(GpuApi::Metal);
+		}
+		let page_size = host.get_page_row_column_count().unwrap_or(PointI { x: 0, y: 0 });
+		HostCapabilities {
+			name: host.get_name().unwrap_or_default(),
+			label: host.get_label().unwrap_or_default(),
+			version: host.get_all::<Version::Property>().unwrap_or_default(),
+			version_label: host.get_version_label().unwrap_or_default(),
+			suites,
+			contexts: all_enums::<SupportedContexts::Property, _>(host),
+			components: all_enums::<SupportedComponents::Property, _>(host),
+			depths: all_enums::<SupportedPixelDepths::Property, _>(host),
+			gpu_apis,
+			is_background: host.get_is_background().unwrap_or_default(),
+			supports_tiles: host.get_supports_tiles().unwrap_or_default(),
+			supports_multi_resolution: host.get_supports_multi_resolution().unwrap_or_default(),
+			supports_multiple_clip_depths: host
+				.get_supports_multiple_clip_depths()
+				.unwrap_or_default(),
+			supports_multiple_clip_pars: host.get_supports_multiple_clip_pars().unwrap_or_default(),
+			temporal_clip_access: host.get_temporal_clip_access().unwrap_or_default(),
+			supports_str_choice: host.get_supports_str_choice().unwrap_or_default(),
+			max_parameters: host.get_max_parameters().unwrap_or(-1),
+			max_pages: host.get_max_pages().unwrap_or(-1),
+			page_rows: page_size.x,
+			page_columns: page_size.y,
+		}
+	}
+
+	pub fn supports_context(&self, context: ImageEffectContext) -> bool {
+		self.contexts.contains(&context)
+	}
+
+	pub fn supports_component(&self, component: ImageComponent) -> bool {
+		self.components.contains(&component)
+	}
+
+	pub fn supports_depth(&self, depth: BitDepth) -> bool {
+		self.depths.contains(&depth)
+	}
+
+	pub fn supports_gpu(&self, api: GpuApi) -> bool {
+		self.gpu_apis.contains(&api)
+	}
+}
+
+mod tests {
+	use super::*;
+	use ofx_sys::*;
+	use std::ffi::{CStr, CString};
+	use std::os::raw::{c_char, c_int};
+	use std::rc::Rc;
+
+	// the properties of a fake host, looked up by name
+	struct FakeHost {
+		ints: Vec<(&'static [u8], Vec<Int>)>,
+		strings: Vec<(&'static [u8], Vec<CString>)>,
+	}
+
+	unsafe fn fake<'a>(properties: OfxPropertySetHandle) -> &'a FakeHost {
+		&*(properties as *const FakeHost)
+	}
+
+	fn find<'a, T>(values: &'a [(&'static [u8], Vec<T>)], name: *const c_char) -> Option<&'a [T]> {
+		let name = unsafe { CStr::from_ptr(name) }.to_bytes_with_nul();
+		values.iter().find(|(key, _)| *key == name).map(|(_, values)| &values[..])
+	}
+
+	unsafe extern "C" fn get_int(
+		properties: OfxPropertySetHandle,
+		name: *const c_char,
+		index: c_int,
+		value: *mut c_int,
+	) -> OfxStatus {
+		match find(&fake(properties).ints, name).and_then(|values| values.get(index as usize)) {
+			Some(found) => {
+				*value = *found;
+				eOfxStatus_OK
+			}
+			None => eOfxStatus_ErrUnknown,
+		}
+	}
+
+	unsafe extern "C" fn get_int_n(
+		properties: OfxPropertySetHandle,
+		name: *const c_char,
+		count: c_int,
+		value: *mut c_int,
+	) -> OfxStatus {
+		match find(&fake(properties).ints, name) {
+			Some(found) if found.len() == count as usize => {
+				std::ptr::copy_nonoverlapping(found.as_ptr(), value, found.len());
+				eOfxStatus_OK
+			}
+			_ => eOfxStatus_ErrUnknown,
+		}
+	}
+
+	unsafe extern "C" fn get_string(
+		properties: OfxPropertySetHandle,
+		name: *const c_char,
+		index: c_int,
+		value: *mut *const c_char,
+	) -> OfxStatus {
+		match find(&fake(properties).strings, name).and_then(|values| values.get(index as usize)) {
+			Some(found) => {
+				*value = found.as_ptr();
+				eOfxStatus_OK
+			}
+			None => eOfxStatus_ErrUnknown,
+		}
+	}
+
+	unsafe extern "C" fn get_dimension(
+		properties: OfxPropertySetHandle,
+		name: *const c_char,
+		count: *mut c_int,
+	) -> OfxStatus {
+		let host = fake(properties);
+		let found = find(&host.ints, name)
+			.map(|values| values.len())
+			.or_else(|| find(&host.strings, name).map(|values| values.len()));
+		match found {
+			Some(found) => {
+				*count = found as c_int;
+				eOfxStatus_OK
+			}
+			None => eOfxStatus_ErrUnknown,
+		}
+	}
+
+	fn read(host: &FakeHost, suites: HostSuites) -> HostCapabilities {
+		let property = OfxPropertySuiteV1 {
+			propSetPointer: None,
+			propSetString: None,
+			propSetDouble: None,
+			propSetInt: None,
+			propSetPointerN: None,
+			propSetStringN: None,
+			propSetDoubleN: None,
+			propSetIntN: None,
+			propGetPointer: None,
+			propGetString: Some(get_string),
+			propGetDouble: None,
+			propGetInt: Some(get_int),
+			propGetPointerN: None,
+			propGetStringN: None,
+			propGetDoubleN: None,
+			propGetIntN: Some(get_int_n),
+			propReset: None,
+			propGetDimension: Some(get_dimension),
+		};
+		let handle = host as *const FakeHost as OfxPropertySetHandle;
+		HostCapabilities::read(&ImageEffectHost::new(handle, Rc::new(property)), suites)
+	}
+
+	fn strings(values: &[&[u8]]) -> Vec<CString> {
+		values
+			.iter()
+			.map(|value| CStr::from_bytes_with_nul(value).unwrap().to_owned())
+			.collect()
+	}
+
+	#[test]
+	fn missing_properties() {
+		let host = FakeHost {
+			ints: Vec::new(),
+			strings: Vec::new(),
+		};
+		let capabilities = read(&host, HostSuites::default());
+		assert_eq!(capabilities.name, "");
+		assert_eq!(capabilities.max_parameters, -1);
+		assert_eq!(capabilities.max_pages, -1);
+		assert_eq!((capabilities.page_rows, capabilities.page_columns), (0, 0));
+		assert!(!capabilities.supports_multiple_clip_depths);
+		assert!(!capabilities.supports_context(ImageEffectContext::Filter));
+		assert!(capabilities.gpu_apis.is_empty());
+	}
+
+	#[test]
+	fn reported_properties() {
+		let host = FakeHost {
+			ints: vec![
+				(kOfxParamHostPropMaxPages, vec![4]),
+				(kOfxParamHostPropMaxParameters, vec![100]),
+				(kOfxParamHostPropPageRowColumnCount, vec![10, 2]),
+				(kOfxImageEffectPropSupportsMultipleClipDepths, vec![1]),
+				(kOfxPropVersion, vec![2, 5]),
+			],
+			strings: vec![
+				(kOfxPropName, strings(&[b"fr.inria.Natron\0"])),
+				(
+					kOfxImageEffectPropSupportedContexts,
+					strings(&[kOfxImageEffectContextFilter, b"NotAContext\0"]),
+				),
+				(kOfxImageEffectPropSupportedPixelDepths, strings(&[kOfxBitDepthFloat])),
+				(kOfxImageEffectPropOpenGLRenderSupported, strings(&[b"needed\0"])),
+				(kOfxImageEffectPropOpenCLRenderSupported, strings(&[b"false\0"])),
+				(kOfxImageEffectPropCudaRenderSupported, strings(&[b"true\0"])),
+			],
+		};
+		let suites = HostSuites {
+			opengl_render: true,
+			..HostSuites::default()
+		};
+		let capabilities = read(&host, suites);
+		assert_eq!(capabilities.name, "fr.inria.Natron");
+		assert_eq!(capabilities.version, vec![2, 5]);
+		assert_eq!((capabilities.max_pages, capabilities.max_parameters), (4, 100));
+		assert_eq!((capabilities.page_rows, capabilities.page_columns), (10, 2));
+		assert!(capabilities.supports_multiple_clip_depths);
+		assert_eq!(capabilities.contexts, vec![ImageEffectContext::Filter]);
+		assert!(capabilities.supports_depth(BitDepth::Float));
+		assert_eq!(capabilities.gpu_apis, vec![GpuApi::OpenGL, GpuApi::Cuda]);
+
+		// OpenGL also needs the render suite
+		let capabilities = read(&host, HostSuites::default());
+		assert_eq!(capabilities.gpu_apis, vec![GpuApi::Cuda]);
+	}
+}
@@ -0,0 +1,231 @@
+use action::*;
+use enums::*;
+use handle::*;
+use property::*;
+use result::*;
+use std::collections::HashMap;
+use types::*;
+
+/// A single parameter or clip change reported by InstanceChanged
+#[derive(Debug, Clone)]
+pub struct InstanceChange {
+	/// `Type::Parameter` or `Type::Clip`
+	pub kind: Type,
+	pub name: String,
+	pub reason: Change,
+	pub time: Time,
+	pub render_scale: PointD,
+}
+
+pub type ChangeHandler = Box<dyn Fn(&mut ImageEffectHandle, &InstanceChange) -> Result<()>>;
+pub type BatchHandler = Box<dyn Fn(&mut ImageEffectHandle, &[InstanceChange]) -> Result<()>>;
+
+/// Routes InstanceChanged actions to handlers registered by parameter or clip name.
+///
+/// Changes reported between BeginInstanceChanged and EndInstanceChanged are
+/// held back and delivered in order when the batch ends, see also `coalesce`.
+///
+/// ```ignore
+/// let changes = ChangeDispatcher::new()
+///     .on_param("scaleComponents", |effect, change| update_ui(effect))
+///     .on_button("reset", |effect, _| reset(effect));
+/// // in Execute::execute
+/// BeginInstanceChanged(..) | InstanceChanged(..) | EndInstanceChanged(..) => {
+///     self.changes.execute(action)
+/// }
+/// ```
+#[derive(Default)]
+pub struct ChangeDispatcher {
+	params: HashMap<String, ChangeHandler>,
+	buttons: HashMap<String, ChangeHandler>,
+	clips: HashMap<String, ChangeHandler>,
+	batch_handler: Option<BatchHandler>,
+	coalesce: bool,
+	// pending changes per effect instance, keyed by the instance handle
+	pending: HashMap<usize, Vec<InstanceChange>>,
+}
+
+impl ChangeDispatcher {
+	pub fn new() -> Self {
+		ChangeDispatcher::default()
+	}
+
+	pub fn on_param<F>(mut self, name: &str, handler: F) -> Self
+	where
+		F: Fn(&mut ImageEffectHandle, &InstanceChange) -> Result<()> + 'static,
+	{
+		self.params.insert(name.to_owned(), Box::new(handler));
+		self
+	}
+
+	/// Called when the push button parameter `name` is pressed. Changes to a
+	/// parameter of another type with that name go to `on_param` handlers.
+	pub fn on_button<F>(mut self, name: &str, handler: F) -> Self
+	where
+		F: Fn(&mut ImageEffectHandle, &InstanceChange) -> Result<()> + 'static,
+	{
+		self.buttons.insert(name.to_owned(), Box::new(handler));
+		self
+	}
+
+	pub fn on_clip<F>(mut self, name: &str, handler: F) -> Self
+	where
+		F: Fn(&mut ImageEffectHandle, &InstanceChange) -> Result<()> + 'static,
+	{
+		self.clips.insert(name.to_owned(), Box::new(handler));
+		self
+	}
+
+	/// Called once per batch with every change in it, before the per-name handlers
+	pub fn on_batch<F>(mut self, handler: F) -> Self
+	where
+		F: Fn(&mut ImageEffectHandle, &[InstanceChange]) -> Result<()> + 'static,
+	{
+		self.batch_handler = Some(Box::new(handler));
+		self
+	}
+
+	/// Delivers each parameter or clip once per batch, with the reason and
+	/// time of its latest change, in the order they first changed
+	pub fn coalesce(mut self) -> Self {
+		self.coalesce = true;
+		self
+	}
+
+	/// Handles the InstanceChanged family of actions. Returns `OK` when a
+	/// handler ran and `REPLY_DEFAULT` otherwise, including for other actions.
+	pub fn execute(&mut self, action: &mut Action) -> Result<Int> {
+		match *action {
+			Action::BeginInstanceChanged(ref effect, _) => {
+				self.pending.insert(effect.key(), Vec::new());
+				OK
+			}
+			Action::InstanceChanged(ref mut effect, ref in_args) => {
+				let change = InstanceChange {
+					kind: in_args.get_type()?,
+					name: in_args.get_name()?,
+					reason: in_args.get_change_reason()?,
+					time: in_args.get_time()?,
+					render_scale: in_args.get_render_scale()?,
+				};
+				if let Some(pending) = self.pending.get_mut(&effect.key()) {
+					queue_change(pending, change, self.coalesce);
+					return OK;
+				}
+				self.dispatch(effect, &[change])
+			}
+			Action::EndInstanceChanged(ref mut effect, _) => {
+				match self.pending.remove(&effect.key()) {
+					Some(changes) => self.dispatch(effect, &changes),
+					None => REPLY_DEFAULT,
+				}
+			}
+			_ => REPLY_DEFAULT,
+		}
+	}
+
+	fn dispatch(&self, effect: &mut ImageEffectHandle, changes: &[InstanceChange]) -> Result<Int> {
+		if changes.is_empty() {
+			return REPLY_DEFAULT;
+		}
+		let mut handled = false;
+		if let Some(ref handler) = self.batch_handler {
+			handler(effect, changes)?;
+			handled = true;
+		}
+		for change in changes {
+			let handler = match change.kind {
+				Type::Clip => self.clips.get(&change.name),
+				_ => match self.buttons.get(&change.name) {
+					Some(handler) if is_push_button(effect, &change.name)? => Some(handler),
+					_ => self.params.get(&change.name),
+				},
+			};
+			if let Some(handler) = handler {
+				handler(effect, change)?;
+				handled = true;
+			}
+		}
+		if handled {
+			OK
+		} else {
+			REPLY_DEFAULT
+		}
+	}
+}
+
+fn is_push_button(effect: &ImageEffectHandle, name: &str) -> Result<bool> {
+	let param = effect.parameter_set()?.param_descriptor(name)?;
+	Ok(param.get_param_type()? == ParamType::PushButton)
+}
+
+fn queue_change(pending: &mut Vec<InstanceChange>, change: InstanceChange, coalesce: bool) {
+	if coalesce {
+		let existing = pending
+			.iter_mut()
+			.find(|queued| queued.kind == change.kind && queued.name == change.name);
+		if let Some(queued) = existing {
+			*queued = change;
+			return;
+		}
+	}
+	pending.push(change);
+}
+
+mod tests {
+	use super::*;
+
+	fn change(kind: Type, name: &str, time: Time) -> InstanceChange {
+		InstanceChange {
+			kind,
+			name: name.to_owned(),
+			reason: Change::UserEdited,
+			time,
+			render_scale: PointD { x: 1.0, y: 1.0 },
+		}
+	}
+
+	fn batch(coalesce: bool) -> Vec<(Type, String, Time)> {
+		let mut pending = Vec::new();
+		queue_change(&mut pending, change(Type::Parameter, "a", 1.0), coalesce);
+		queue_change(&mut pending, change(Type::Clip, "a", 1.0), coalesce);
+		queue_change(&mut pending, change(Type::Parameter, "b", 2.0), coalesce);
+		queue_change(&mut pending, change(Type::Parameter, "a", 3.0), coalesce);
+		pending
+			.into_iter()
+			.map(|change| (change.kind, change.name, change.time))
+			.collect()
+	}
+
+	fn summary(changes: &[(Type, &str, Time)]) -> Vec<(Type, String, Time)> {
+		changes
+			.iter()
+			.map(|&(kind, name, time)| (kind, name.to_owned(), time))
+			.collect()
+	}
+
+	#[test]
+	fn batched_changes_are_kept_in_order() {
+		assert_eq!(
+			batch(false),
+			summary(&[
+				(Type::Parameter, "a", 1.0),
+				(Type::Clip, "a", 1.0),
+				(Type::Parameter, "b", 2.0),
+				(Type::Parameter, "a", 3.0)
+			])
+		);
+	}
+
+	#[test]
+	fn batched_changes_are_coalesced_by_name() {
+		assert_eq!(
+			batch(true),
+			summary(&[
+				(Type::Parameter, "a", 3.0),
+				(Type::Clip, "a", 1.0),
+				(Type::Parameter, "b", 2.0)
+			])
+		);
+	}
+}
@@ -0,0 +1,567 @@
+use types::*;
+
+/// A curve encoding linear light into code values. Rec.709 is the camera
+/// curve (OETF); PQ encodes absolute light where 1.0 is 10000 cd/m²; HLG
+/// takes scene light in 0..1. The log curves are Cineon's printing density,
+/// ARRI LogC3 at EI 800 and Sony S-Log3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferFunction {
+	Linear,
+	Srgb,
+	Rec709,
+	Pq,
+	Hlg,
+	Cineon,
+	LogC,
+	SLog3,
+}
+
+// PQ, SMPTE ST 2084
+const PQ_M1: f32 = 2610.0 / 16384.0;
+const PQ_M2: f32 = 2523.0 / 4096.0 * 128.0;
+const PQ_C1: f32 = 3424.0 / 4096.0;
+const PQ_C2: f32 = 2413.0 / 4096.0 * 32.0;
+const PQ_C3: f32 = 2392.0 / 4096.0 * 32.0;
+
+// HLG, BT.2100
+const HLG_A: f32 = 0.178_832_77;
+const HLG_B: f32 = 0.284_668_92;
+const HLG_C: f32 = 0.559_910_7;
+
+// LogC3, EI 800
+const LOGC_CUT: f32 = 0.010_591;
+const LOGC_A: f32 = 5.555_556;
+const LOGC_B: f32 = 0.052_272;
+const LOGC_C: f32 = 0.247_19;
+const LOGC_D: f32 = 0.385_537;
+const LOGC_E: f32 = 5.367_655;
+const LOGC_F: f32 = 0.092_809;
+
+// Cineon, 10 bit code values of reference white and black
+const CINEON_WHITE: f32 = 685.0;
+const CINEON_BLACK: f32 = 95.0;
+const CINEON_SCALE: f32 = 0.002 / 0.6;
+
+fn cineon_black() -> f32 {
+	10f32.powf((CINEON_BLACK - CINEON_WHITE) * CINEON_SCALE)
+}
+
+// S-Log3 code value of its toe
+const SLOG3_CUT: f32 = 171.210_3;
+
+// sRGB and Rec.709 are extended to negatives by symmetry
+fn symmetric(x: f32, curve: impl Fn(f32) -> f32) -> f32 {
+	if x < 0.0 {
+		-curve(-x)
+	} else {
+		curve(x)
+	}
+}
+
+impl TransferFunction {
+	/// Linear to encoded
+	pub fn encode(self, x: f32) -> f32 {
+		match self {
+			TransferFunction::Linear => x,
+			TransferFunction::Srgb => symmetric(x, |x| {
+				if x <= 0.003_130_8 {
+					12.92 * x
+				} else {
+					1.055 * x.powf(1.0 / 2.4) - 0.055
+				}
+			}),
+			TransferFunction::Rec709 => symmetric(x, |x| {
+				if x < 0.018 {
+					4.5 * x
+				} else {
+					1.099 * x.powf(0.45) - 0.099
+				}
+			}),
+			TransferFunction::Pq => {
+				let y = x.max(0.0).powf(PQ_M1);
+				((PQ_C1 + PQ_C2 * y) / (1.0 + PQ_C3 * y)).powf(PQ_M2)
+			}
+			TransferFunction::Hlg => {
+				let x = x.max(0.0);
+				if x <= 1.0 / 12.0 {
+					(3.0 * x).sqrt()
+				} else {
+					HLG_A * (12.0 * x - HLG_B).ln() + HLG_C
+				}
+			}
+			TransferFunction::Cineon => {
+				let black = cineon_black();
+				let density = (x * (1.0 - black) + black).max(1e-10).log10();
+				(CINEON_WHITE + density / CINEON_SCALE) / 1023.0
+			}
+			TransferFunction::LogC => {
+				if x > LOGC_CUT {
+					LOGC_C * (LOGC_A * x + LOGC_B).log10() + LOGC_D
+				} else {
+					LOGC_E * x + LOGC_F
+				}
+			}
+			TransferFunction::SLog3 => {
+				if x >= 0.011_25 {
+					(420.0 + ((x + 0.01) / 0.19).log10() * 261.5) / 1023.0
+				} else {
+					(x * (SLOG3_CUT - 95.0) / 0.011_25 + 95.0) / 1023.0
+				}
+			}
+		}
+	}
+
+	/// Encoded to linear
+	pub fn decode(self, v: f32) -> f32 {
+		match self {
+			TransferFunction::Linear => v,
+			TransferFunction::Srgb => symmetric(v, |v| {
+				if v <= 0.040_45 {
+					v / 12.92
+				} else {
+					((v + 0.055) / 1.055).powf(2.4)
+				}
+			}),
+			TransferFunction::Rec709 => symmetric(v, |v| {
+				if v < 0.081 {
+					v / 4.5
+				} else {
+					((v + 0.099) / 1.099).powf(1.0 / 0.45)
+				}
+			}),
+			TransferFunction::Pq => {
+				let e = v.max(0.0).powf(1.0 / PQ_M2);
+				((e - PQ_C1).max(0.0) / (PQ_C2 - PQ_C3 * e)).powf(1.0 / PQ_M1)
+			}
+			TransferFunction::Hlg => {
+				let v = v.max(0.0);
+				if v <= 0.5 {
+					v * v / 3.0
+				} else {
+					(((v - HLG_C) / HLG_A).exp() + HLG_B) / 12.0
+				}
+			}
+			TransferFunction::Cineon => {
+				let black = cineon_black();
+				(10f32.powf((1023.0 * v - CINEON_WHITE) * CINEON_SCALE) - black) / (1.0 - black)
+			}
+			TransferFunction::LogC => {
+				if v > LOGC_E * LOGC_CUT + LOGC_F {
+					(10f32.powf((v - LOGC_D) / LOGC_C) - LOGC_B) / LOGC_A
+				} else {
+					(v - LOGC_F) / LOGC_E
+				}
+			}
+			TransferFunction::SLog3 => {
+				if v >= SLOG3_CUT / 1023.0 {
+					10f32.powf((v * 1023.0 - 420.0) / 261.5) * 0.19 - 0.01
+				} else {
+					(v * 1023.0 - 95.0) * 0.011_25 / (SLOG3_CUT - 95.0)
+				}
+			}
+		}
+	}
+
+	/// Encodes the colour of `row` in place, leaving alpha as it is
+	pub fn encode_row(self, row: &mut [RGBAColourF]) {
+		if self != TransferFunction::Linear {
+			for pixel in row {
+				pixel.r = self.encode(pixel.r);
+				pixel.g = self.encode(pixel.g);
+				pixel.b = self.encode(pixel.b);
+			}
+		}
+	}
+
+	/// Decodes the colour of `row` in place, leaving alpha as it is
+	pub fn decode_row(self, row: &mut [RGBAColourF]) {
+		if self != TransferFunction::Linear {
+			for pixel in row {
+				pixel.r = self.decode(pixel.r);
+				pixel.g = self.decode(pixel.g);
+				pixel.b = self.decode(pixel.b);
+			}
+		}
+	}
+}
+
+/// A 3 by 3 matrix acting on column vectors of RGB or XYZ, row by row
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Matrix3(pub [[f64; 3]; 3]);
+
+impl Matrix3 {
+	pub fn identity() -> Self {
+		Matrix3::diagonal([1.0, 1.0, 1.0])
+	}
+
+	pub fn diagonal(d: [f64; 3]) -> Self {
+		Matrix3([[d[0], 0.0, 0.0], [0.0, d[1], 0.0], [0.0, 0.0, d[2]]])
+	}
+
+	/// `self` applied after `other`
+	pub fn multiply(&self, other: &Matrix3) -> Self {
+		let (a, b) = (&self.0, &other.0);
+		let mut m = [[0.0; 3]; 3];
+		for (i, row) in m.iter_mut().enumerate() {
+			for (j, value) in row.iter_mut().enumerate() {
+				*value = a[i][0] * b[0][j] + a[i][1] * b[1][j] + a[i][2] * b[2][j];
+			}
+		}
+		Matrix3(m)
+	}
+
+	/// None for singular matrices
+	pub fn inverse(&self) -> Option<Self> {
+		let m = &self.0;
+		let cofactor = |i: usize, j: usize| {
+			let (i1, i2) = ((i + 1) % 3, (i + 2) % 3);
+			let (j1, j2) = ((j + 1) % 3, (j + 2) % 3);
+			m[i1][j1] * m[i2][j2] - m[i1][j2] * m[i2][j1]
+		};
+		let determinant = m[0][0] * cofactor(0, 0) + m[0][1] * cofactor(0, 1) + m[0][2] * cofactor(0, 2);
+		if determinant.abs() < 1e-12 {
+			return None;
+		}
+		let mut inverse = [[0.0; 3]; 3];
+		for (i, row) in inverse.iter_mut().enumerate() {
+			for (j, value) in row.iter_mut().enumerate() {
+				*value = cofactor(j, i) / determinant;
+			}
+		}
+		Some(Matrix3(inverse))
+	}
+
+	pub fn apply(&self, v: [f64; 3]) -> [f64; 3] {
+		let m = &self.0;
+		[
+			m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+			m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+			m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2],
+		]
+	}
+
+	fn to_f32(self) -> [[f32; 3]; 3] {
+		let m = &self.0;
+		let row = |i: usize| [m[i][0] as f32, m[i][1] as f32, m[i][2] as f32];
+		[row(0), row(1), row(2)]
+	}
+
+	/// Transforms the colour of `row` in place, leaving alpha as it is. With
+	/// the `simd` feature this uses SSE on x86_64.
+	pub fn apply_row(&self, row: &mut [RGBAColourF]) {
+		apply_row(&self.to_f32(), row);
+	}
+}
+
+#[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+fn apply_row(m: &[[f32; 3]; 3], row: &mut [RGBAColourF]) {
+	for pixel in row {
+		let (r, g, b) = (pixel.r, pixel.g, pixel.b);
+		pixel.r = m[0][0] * r + m[0][1] * g + m[0][2] * b;
+		pixel.g = m[1][0] * r + m[1][1] * g + m[1][2] * b;
+		pixel.b = m[2][0] * r + m[2][1] * g + m[2][2] * b;
+	}
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+fn apply_row(m: &[[f32; 3]; 3], row: &mut [RGBAColourF]) {
+	use std::arch::x86_64::*;
+	// SSE is always there on x86_64; RGBAColourF is four packed f32
+	unsafe {
+		let r = _mm_setr_ps(m[0][0], m[1][0], m[2][0], 0.0);
+		let g = _mm_setr_ps(m[0][1], m[1][1], m[2][1], 0.0);
+		let b = _mm_setr_ps(m[0][2], m[1][2], m[2][2], 0.0);
+		let a = _mm_setr_ps(0.0, 0.0, 0.0, 1.0);
+		for pixel in row {
+			let p = pixel as *mut RGBAColourF as *mut f32;
+			let v = _mm_loadu_ps(p);
+			let rg = _mm_add_ps(
+				_mm_mul_ps(r, _mm_shuffle_ps(v, v, 0x00)),
+				_mm_mul_ps(g, _mm_shuffle_ps(v, v, 0x55)),
+			);
+			let ba = _mm_add_ps(
+				_mm_mul_ps(b, _mm_shuffle_ps(v, v, 0xaa)),
+				_mm_mul_ps(a, _mm_shuffle_ps(v, v, 0xff)),
+			);
+			_mm_storeu_ps(p, _mm_add_ps(rg, ba));
+		}
+	}
+}
+
+/// Cone response models for chromatic adaptation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Adaptation {
+	Bradford,
+	Cat02,
+}
+
+impl Adaptation {
+	fn cone_response(self) -> Matrix3 {
+		match self {
+			Adaptation::Bradford => Matrix3([
+				[0.8951, 0.2664, -0.1614],
+				[-0.7502, 1.7135, 0.0367],
+				[0.0389, -0.0685, 1.0296],
+			]),
+			Adaptation::Cat02 => Matrix3([
+				[0.7328, 0.4296, -0.1624],
+				[-0.7036, 1.6975, 0.0061],
+				[0.0030, 0.0136, 0.9834],
+			]),
+		}
+	}
+
+	/// Maps XYZ seen under the white point `from` to XYZ under `to`, both
+	/// as xy chromaticities
+	pub fn matrix(self, from: (f64, f64), to: (f64, f64)) -> Matrix3 {
+		let cone = self.cone_response();
+		let source = cone.apply(xy_to_xyz(from));
+		let destination = cone.apply(xy_to_xyz(to));
+		let scale = Matrix3::diagonal([
+			destination[0] / source[0],
+			destination[1] / source[1],
+			destination[2] / source[2],
+		]);
+		cone.inverse()
+			.expect("cone response matrices are invertible")
+			.multiply(&scale.multiply(&cone))
+	}
+}
+
+// XYZ of chromaticity `xy` with Y of 1
+fn xy_to_xyz((x, y): (f64, f64)) -> [f64; 3] {
+	[x / y, 1.0, (1.0 - x - y) / y]
+}
+
+const D65: (f64, f64) = (0.3127, 0.3290);
+const ACES_WHITE: (f64, f64) = (0.32168, 0.33767);
+
+/// RGB primaries and white point
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gamut {
+	/// Also sRGB
+	Rec709,
+	Rec2020,
+	/// Display P3, with a D65 white
+	P3D65,
+	AcesAp0,
+	AcesAp1,
+	ArriWideGamut3,
+	SGamut3,
+}
+
+impl Gamut {
+	/// xy of red, green, blue and white
+	pub fn chromaticities(self) -> [(f64, f64); 4] {
+		match self {
+			Gamut::Rec709 => [(0.64, 0.33), (0.30, 0.60), (0.15, 0.06), D65],
+			Gamut::Rec2020 => [(0.708, 0.292), (0.170, 0.797), (0.131, 0.046), D65],
+			Gamut::P3D65 => [(0.680, 0.320), (0.265, 0.690), (0.150, 0.060), D65],
+			Gamut::AcesAp0 => [(0.7347, 0.2653), (0.0, 1.0), (0.0001, -0.0770), ACES_WHITE],
+			Gamut::AcesAp1 => [(0.713, 0.293), (0.165, 0.830), (0.128, 0.044), ACES_WHITE],
+			Gamut::ArriWideGamut3 => [(0.6840, 0.3130), (0.2210, 0.8480), (0.0861, -0.1020), D65],
+			Gamut::SGamut3 => [(0.730, 0.280), (0.140, 0.855), (0.100, -0.050), D65],
+		}
+	}
+
+	pub fn white(self) -> (f64, f64) {
+		self.chromaticities()[3]
+	}
+
+	/// Linear RGB to CIE XYZ, white having Y of 1
+	pub fn to_xyz(self) -> Matrix3 {
+		let [red, green, blue, white] = self.chromaticities();
+		let (r, g, b) = (xy_to_xyz(red), xy_to_xyz(green), xy_to_xyz(blue));
+		let primaries = Matrix3([[r[0], g[0], b[0]], [r[1], g[1], b[1]], [r[2], g[2], b[2]]]);
+		let scale = primaries
+			.inverse()
+			.expect("primaries are independent")
+			.apply(xy_to_xyz(white));
+		primaries.multiply(&Matrix3::diagonal(scale))
+	}
+
+	pub fn from_xyz(self) -> Matrix3 {
+		self.to_xyz().inverse().expect("primaries are independent")
+	}
+
+	/// Linear RGB in this gamut to linear RGB in `to`, adapting the white
+	/// point with `adaptation` when they differ
+	pub fn conversion(self, to: Gamut, adaptation: Adaptation) -> Matrix3 {
+		let xyz = if self.white() == to.white() {
+			Matrix3::identity()
+		} else {
+			adaptation.matrix(self.white(), to.white())
+		};
+		to.from_xyz().multiply(&xyz.multiply(&self.to_xyz()))
+	}
+
+	/// Weights of linear red, green and blue in relative luminance
+	pub fn luminance_coefficients(self) -> [f64; 3] {
+		self.to_xyz().0[1]
+	}
+
+	/// Relative luminance of a linear colour
+	pub fn luminance(self, colour: &RGBAColourF) -> f32 {
+		let [r, g, b] = self.luminance_coefficients();
+		r as f32 * colour.r + g as f32 * colour.g + b as f32 * colour.b
+	}
+}
+
+/// A gamut and how its values are encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColourSpace {
+	pub gamut: Gamut,
+	pub transfer: TransferFunction,
+}
+
+impl ColourSpace {
+	pub fn new(gamut: Gamut, transfer: TransferFunction) -> Self {
+		ColourSpace { gamut, transfer }
+	}
+}
+
+/// Converts rows between two colour spaces: decodes, changes gamut with a
+/// Bradford adaptation, then encodes. Colour should be unpremultiplied,
+/// see `with_unpremultiplied`.
+#[derive(Debug, Clone, Copy)]
+pub struct ColourConversion {
+	from: TransferFunction,
+	matrix: Matrix3,
+	to: TransferFunction,
+}
+
+impl ColourConversion {
+	pub fn new(from: ColourSpace, to: ColourSpace) -> Self {
+		ColourConversion {
+			from: from.transfer,
+			matrix: from.gamut.conversion(to.gamut, Adaptation::Bradford),
+			to: to.transfer,
+		}
+	}
+
+	pub fn apply_row(&self, row: &mut [RGBAColourF]) {
+		self.from.decode_row(row);
+		if self.matrix != Matrix3::identity() {
+			self.matrix.apply_row(row);
+		}
+		self.to.encode_row(row);
+	}
+}
+
+mod tests {
+	use super::*;
+
+	fn assert_close(a: f64, b: f64, tolerance: f64) {
+		assert!((a - b).abs() < tolerance, "{} != {}", a, b);
+	}
+
+	const CURVES: [TransferFunction; 8] = [
+		TransferFunction::Linear,
+		TransferFunction::Srgb,
+		TransferFunction::Rec709,
+		TransferFunction::Pq,
+		TransferFunction::Hlg,
+		TransferFunction::Cineon,
+		TransferFunction::LogC,
+		TransferFunction::SLog3,
+	];
+
+	#[test]
+	fn curves_round_trip() {
+		for curve in &CURVES {
+			for x in &[0.0, 0.001, 0.01, 0.05, 0.18, 0.5, 1.0] {
+				let decoded = curve.decode(curve.encode(*x));
+				assert_close(f64::from(decoded), f64::from(*x), 1e-4 * x.max(1.0) as f64);
+			}
+		}
+	}
+
+	#[test]
+	fn known_values() {
+		let encode = |curve: TransferFunction, x: f32| f64::from(curve.encode(x));
+		assert_close(encode(TransferFunction::Srgb, 0.18), 0.461_356, 1e-5);
+		assert_close(encode(TransferFunction::Srgb, -0.18), -0.461_356, 1e-5);
+		assert_close(encode(TransferFunction::Rec709, 1.0), 1.0, 1e-6);
+		assert_close(encode(TransferFunction::Pq, 1.0), 1.0, 1e-5);
+		assert_close(encode(TransferFunction::Pq, 0.01), 0.508_078, 1e-4);
+		assert_close(encode(TransferFunction::Hlg, 1.0 / 12.0), 0.5, 1e-6);
+		assert_close(encode(TransferFunction::Hlg, 1.0), 1.0, 1e-5);
+		assert_close(encode(TransferFunction::Cineon, 1.0), 685.0 / 1023.0, 1e-5);
+		assert_close(encode(TransferFunction::Cineon, 0.0), 95.0 / 1023.0, 1e-5);
+		assert_close(encode(TransferFunction::LogC, 0.18), 0.391_007, 1e-5);
+		assert_close(encode(TransferFunction::SLog3, 0.18), 420.0 / 1023.0, 1e-5);
+	}
+
+	#[test]
+	fn rec709_luminance() {
+		let [r, g, b] = Gamut::Rec709.luminance_coefficients();
+		assert_close(r, 0.2126, 1e-4);
+		assert_close(g, 0.7152, 1e-4);
+		assert_close(b, 0.0722, 1e-4);
+		let white = RGBAColourF {
+			r: 1.0,
+			g: 1.0,
+			b: 1.0,
+			a: 0.0,
+		};
+		assert_close(f64::from(Gamut::Rec2020.luminance(&white)), 1.0, 1e-5);
+	}
+
+	#[test]
+	fn conversions_keep_white() {
+		let gamuts = [
+			Gamut::Rec709,
+			Gamut::Rec2020,
+			Gamut::P3D65,
+			Gamut::AcesAp0,
+			Gamut::AcesAp1,
+			Gamut::ArriWideGamut3,
+			Gamut::SGamut3,
+		];
+		for from in &gamuts {
+			for to in &gamuts {
+				let white = from.conversion(*to, Adaptation::Bradford).apply([1.0, 1.0, 1.0]);
+				for value in &white {
+					assert_close(*value, 1.0, 1e-6);
+				}
+			}
+		}
+		let m = Gamut::Rec709.conversion(Gamut::Rec2020, Adaptation::Cat02).0;
+		assert_close(m[0][0], 0.6274, 1e-4);
+		assert_close(m[0][1], 0.3293, 1e-4);
+		assert_close(m[2][2], 0.8956, 1e-4);
+	}
+
+	#[test]
+	fn adaptation_maps_white_to_white() {
+		let adapted = Adaptation::Bradford.matrix(D65, ACES_WHITE).apply(xy_to_xyz(D65));
+		let expected = xy_to_xyz(ACES_WHITE);
+		for (adapted, expected) in adapted.iter().zip(&expected) {
+			assert_close(*adapted, *expected, 1e-9);
+		}
+	}
+
+	#[test]
+	fn rows() {
+		let mut row = [
+			RGBAColourF {
+				r: 0.5,
+				g: 0.2,
+				b: 0.8,
+				a: 0.5,
+			};
+			3
+		];
+		let srgb = ColourSpace::new(Gamut::Rec709, TransferFunction::Srgb);
+		let logc = ColourSpace::new(Gamut::ArriWideGamut3, TransferFunction::LogC);
+		ColourConversion::new(srgb, logc).apply_row(&mut row);
+		assert!(row[2].r != 0.5 && row[2].a == 0.5);
+		ColourConversion::new(logc, srgb).apply_row(&mut row);
+		for pixel in &row {
+			assert_close(f64::from(pixel.r), 0.5, 1e-4);
+			assert_close(f64::from(pixel.g), 0.2, 1e-4);
+			assert_close(f64::from(pixel.b), 0.8, 1e-4);
+			assert_eq!(pixel.a, 0.5);
+		}
+	}
+}
@@ -0,0 +1,651 @@
+use coords::*;
+use image::*;
+use kernel::*;
+use sampler::*;
+use tile::intersect;
+use types::*;
+
+// filters work on RGBA floats whatever the storage
+type Colour = [f32; 4];
+
+const BLACK: Colour = [0.0; 4];
+
+/// Weights of a one dimensional kernel of odd length, centred on the middle one.
+/// Filters convolve, flipping the weights, so that a single lit pixel
+/// spreads into the weights in their own order.
+#[derive(Debug, Clone)]
+pub struct Kernel1D {
+	weights: Vec<f32>,
+}
+
+impl Kernel1D {
+	pub fn new(weights: Vec<f32>) -> Self {
+		assert!(weights.len() % 2 == 1, "kernels have an odd number of weights");
+		Kernel1D { weights }
+	}
+
+	/// Averages `2 * radius + 1` pixels
+	pub fn box_filter(radius: usize) -> Self {
+		let size = 2 * radius + 1;
+		Kernel1D::new(vec![1.0 / size as f32; size])
+	}
+
+	/// Normalised, cut off at `gaussian_radius(sigma)`
+	pub fn gaussian(sigma: Double) -> Self {
+		let radius = gaussian_radius(sigma) as isize;
+		if radius == 0 {
+			return Kernel1D::new(vec![1.0]);
+		}
+		let weights: Vec<f32> = (-radius..=radius)
+			.map(|x| (-(x * x) as Double / (2.0 * sigma * sigma)).exp() as f32)
+			.collect();
+		let sum: f32 = weights.iter().sum();
+		Kernel1D::new(weights.iter().map(|weight| weight / sum).collect())
+	}
+
+	pub fn radius(&self) -> usize {
+		self.weights.len() / 2
+	}
+
+	pub fn weights(&self) -> &[f32] {
+		&self.weights
+	}
+}
+
+/// Weights of a two dimensional kernel of odd width and height, row by row,
+/// flipped in both directions when convolving like `Kernel1D`
+#[derive(Debug, Clone)]
+pub struct Kernel2D {
+	width: usize,
+	height: usize,
+	weights: Vec<f32>,
+}
+
+impl Kernel2D {
+	pub fn new(width: usize, height: usize, weights: Vec<f32>) -> Self {
+		assert!(width % 2 == 1 && height % 2 == 1, "kernels have an odd size");
+		assert_eq!(weights.len(), width * height);
+		Kernel2D {
+			width,
+			height,
+			weights,
+		}
+	}
+
+	pub fn radius_x(&self) -> usize {
+		self.width / 2
+	}
+
+	pub fn radius_y(&self) -> usize {
+		self.height / 2
+	}
+}
+
+/// How far a Gaussian of `sigma` pixels reaches, three standard deviations
+pub fn gaussian_radius(sigma: Double) -> usize {
+	if sigma > 0.0 {
+		(3.0 * sigma).ceil() as usize
+	} else {
+		0
+	}
+}
+
+/// The source region needed to filter `roi` with kernels reaching `radius_x`
+/// and `radius_y` pixels of images rendered with `coordinates`, for
+/// GetRegionsOfInterest. Both regions are in canonical coordinates.
+pub fn region_of_interest(
+	roi: RectD,
+	radius_x: usize,
+	radius_y: usize,
+	coordinates: &CoordinateSystem,
+) -> RectD {
+	let radius = coordinates.pixel_to_canonical(PointD {
+		x: radius_x as Double,
+		y: radius_y as Double,
+	});
+	RectD {
+		x1: roi.x1 - radius.x,
+		y1: roi.y1 - radius.y,
+		x2: roi.x2 + radius.x,
+		y2: roi.y2 + radius.y,
+	}
+}
+
+fn to_colour<T: KernelFormat>(pixel: &T) -> Colour {
+	let colour = pixel.to_rgba();
+	[colour.r, colour.g, colour.b, colour.a]
+}
+
+fn from_colour<T: KernelFormat>(colour: &Colour) -> T {
+	T::from_rgba(&RGBAColourF {
+		r: colour[0],
+		g: colour[1],
+		b: colour[2],
+		a: colour[3],
+	})
+}
+
+// the pixels of `rect` row by row, `edge` deciding those outside `src`
+fn read_rect<T>(src: &ImageDescriptor<T>, rect: RectI, edge: EdgeMode) -> Vec<Colour>
+where
+	T: KernelFormat,
+{
+	let bounds = src.bounds();
+	let empty = bounds.x1 >= bounds.x2 || bounds.y1 >= bounds.y2;
+	let mut pixels = Vec::with_capacity(((rect.x2 - rect.x1) * (rect.y2 - rect.y1)) as usize);
+	for y in rect.y1..rect.y2 {
+		let row = if empty {
+			None
+		} else {
+			edge.wrap(y, bounds.y1, bounds.y2).map(|y| src.row(y))
+		};
+		pixels.extend((rect.x1..rect.x2).map(|x| {
+			row.and_then(|row| edge.wrap(x, bounds.x1, bounds.x2).map(|x| row[(x - bounds.x1) as usize]))
+				.map_or(BLACK, |pixel| to_colour(&pixel))
+		}));
+	}
+	pixels
+}
+
+// writes `pixels`, row by row, to `window` of `dst`
+fn write_rect<T>(dst: &mut ImageBufferMut<T>, window: RectI, pixels: &[Colour])
+where
+	T: KernelFormat,
+{
+	let x1 = (window.x1 - dst.bounds().x1) as usize;
+	let width = (window.x2 - window.x1) as usize;
+	for (y, pixels) in (window.y1..window.y2).zip(pixels.chunks(width)) {
+		for (dst, colour) in dst.row_mut(y)[x1..x1 + width].iter_mut().zip(pixels) {
+			*dst = from_colour(colour);
+		}
+	}
+}
+
+// Filters `window` of `dst`, clipped to its bounds, with a horizontal then a
+// vertical pass. Each pass reads lines `2 * radius` longer than it writes.
+fn separable<T, H, V>(
+	src: &ImageDescriptor<T>,
+	dst: &mut ImageBufferMut<T>,
+	window: RectI,
+	edge: EdgeMode,
+	(radius_x, radius_y): (usize, usize),
+	horizontal: H,
+	vertical: V,
+) where
+	T: KernelFormat,
+	H: Fn(&[Colour], &mut [Colour]),
+	V: Fn(&[Colour], &mut [Colour]),
+{
+	let window = intersect(window, dst.bounds());
+	let width = (window.x2 - window.x1) as usize;
+	let height = (window.y2 - window.y1) as usize;
+	if width == 0 || height == 0 {
+		return;
+	}
+	let rows = height + 2 * radius_y;
+	let input = read_rect(
+		src,
+		RectI {
+			x1: window.x1 - radius_x as Int,
+			y1: window.y1 - radius_y as Int,
+			x2: window.x2 + radius_x as Int,
+			y2: window.y2 + radius_y as Int,
+		},
+		edge,
+	);
+	let mut across = vec![BLACK; width * rows];
+	for (input, output) in input.chunks(width + 2 * radius_x).zip(across.chunks_mut(width)) {
+		horizontal(input, output);
+	}
+	let mut column = vec![BLACK; rows];
+	let mut filtered = vec![BLACK; height];
+	let mut output = vec![BLACK; width * height];
+	for x in 0..width {
+		for (y, colour) in column.iter_mut().enumerate() {
+			*colour = across[y * width + x];
+		}
+		vertical(&column, &mut filtered);
+		for (y, colour) in filtered.iter().enumerate() {
+			output[y * width + x] = *colour;
+		}
+	}
+	write_rect(dst, window, &output);
+}
+
+// the last weight applies to the first pixel of each window
+fn convolve_line(weights: &[f32], input: &[Colour], output: &mut [Colour]) {
+	for (output, input) in output.iter_mut().zip(input.windows(weights.len())) {
+		let mut sum = BLACK;
+		for (weight, colour) in weights.iter().rev().zip(input) {
+			for (sum, value) in sum.iter_mut().zip(colour) {
+				*sum += weight * value;
+			}
+		}
+		*output = sum;
+	}
+}
+
+fn extremum_line(radius: usize, pick: fn(f32, f32) -> f32, input: &[Colour], output: &mut [Colour]) {
+	for (output, input) in output.iter_mut().zip(input.windows(2 * radius + 1)) {
+		let mut extremum = input[0];
+		for colour in &input[1..] {
+			for (extremum, value) in extremum.iter_mut().zip(colour) {
+				*extremum = pick(*extremum, *value);
+			}
+		}
+		*output = extremum;
+	}
+}
+
+// Young and van Vliet's recursive approximation of a Gaussian: a causal then
+// an anticausal third order filter, with feedback normalised by b0
+struct Recursive {
+	gain: f32,
+	feedback: [f32; 3],
+}
+
+impl Recursive {
+	// None below half a pixel, where the approximation breaks down
+	fn new(sigma: Double) -> Option<Self> {
+		if sigma < 0.5 {
+			return None;
+		}
+		let q = if sigma >= 2.5 {
+			0.98711 * sigma - 0.96330
+		} else {
+			3.97156 - 4.14554 * (1.0 - 0.26891 * sigma).sqrt()
+		};
+		let (q2, q3) = (q * q, q * q * q);
+		let b0 = 1.57825 + 2.44413 * q + 1.4281 * q2 + 0.422205 * q3;
+		let feedback = [
+			(2.44413 * q + 2.85619 * q2 + 1.26661 * q3) / b0,
+			-(1.4281 * q2 + 1.26661 * q3) / b0,
+			0.422205 * q3 / b0,
+		];
+		Some(Recursive {
+			gain: (1.0 - feedback.iter().sum::<Double>()) as f32,
+			feedback: [feedback[0] as f32, feedback[1] as f32, feedback[2] as f32],
+		})
+	}
+
+	// filters `line` in place in the direction of `order`, starting from a
+	// steady state on the first value
+	fn pass<I>(&self, line: &mut [Colour], order: I)
+	where
+		I: Iterator<Item = usize>,
+	{
+		let mut history = None;
+		for i in order {
+			let [w1, w2, w3] = *history.get_or_insert([line[i]; 3]);
+			let mut w = BLACK;
+			for c in 0..4 {
+				w[c] = self.gain * line[i][c]
+					+ self.feedback[0] * w1[c]
+					+ self.feedback[1] * w2[c]
+					+ self.feedback[2] * w3[c];
+			}
+			line[i] = w;
+			history = Some([w, w1, w2]);
+		}
+	}
+
+	fn line(&self, radius: usize, input: &[Colour], output: &mut [Colour]) {
+		let mut line = input.to_vec();
+		self.pass(&mut line, 0..input.len());
+		self.pass(&mut line, (0..input.len()).rev());
+		output.copy_from_slice(&line[radius..radius + output.len()]);
+	}
+}
+
+/// Convolves `src` with `horizontal` then `vertical`, writing `window` of
+/// `dst`. `edge` decides the pixels read outside of `src`.
+pub fn convolve<T>(
+	src: &ImageDescriptor<T>,
+	dst: &mut ImageBufferMut<T>,
+	window: RectI,
+	horizontal: &Kernel1D,
+	vertical: &Kernel1D,
+	edge: EdgeMode,
+) where
+	T: KernelFormat,
+{
+	separable(
+		src,
+		dst,
+		window,
+		edge,
+		(horizontal.radius(), vertical.radius()),
+		|input, output| convolve_line(&horizontal.weights, input, output),
+		|input, output| convolve_line(&vertical.weights, input, output),
+	);
+}
+
+/// Convolves `src` with a kernel that does not separate, see `convolve`
+pub fn convolve_2d<T>(
+	src: &ImageDescriptor<T>,
+	dst: &mut ImageBufferMut<T>,
+	window: RectI,
+	kernel: &Kernel2D,
+	edge: EdgeMode,
+) where
+	T: KernelFormat,
+{
+	let window = intersect(window, dst.bounds());
+	let width = (window.x2 - window.x1) as usize;
+	let height = (window.y2 - window.y1) as usize;
+	if width == 0 || height == 0 {
+		return;
+	}
+	let (radius_x, radius_y) = (kernel.radius_x() as Int, kernel.radius_y() as Int);
+	let input = read_rect(
+		src,
+		RectI {
+			x1: window.x1 - radius_x,
+			y1: window.y1 - radius_y,
+			x2: window.x2 + radius_x,
+			y2: window.y2 + radius_y,
+		},
+		edge,
+	);
+	let input_width = width + kernel.width - 1;
+	let mut output = vec![BLACK; width * height];
+	for (i, output) in output.iter_mut().enumerate() {
+		let (x, y) = (i % width, i / width);
+		// flipped rows and columns, as in `convolve_line`
+		for (row, weights) in kernel.weights.chunks(kernel.width).rev().enumerate() {
+			let start = (y + row) * input_width + x;
+			for (weight, colour) in weights.iter().rev().zip(&input[start..start + kernel.width]) {
+				for (sum, value) in output.iter_mut().zip(colour) {
+					*sum += weight * value;
+				}
+			}
+		}
+	}
+	write_rect(dst, window, &output);
+}
+
+/// Averages `2 * radius + 1` pixels in each direction, see `convolve`
+pub fn box_blur<T>(
+	src: &ImageDescriptor<T>,
+	dst: &mut ImageBufferMut<T>,
+	window: RectI,
+	radius_x: usize,
+	radius_y: usize,
+	edge: EdgeMode,
+) where
+	T: KernelFormat,
+{
+	let horizontal = Kernel1D::box_filter(radius_x);
+	let vertical = Kernel1D::box_filter(radius_y);
+	convolve(src, dst, window, &horizontal, &vertical, edge);
+}
+
+/// Gaussian blur with standard deviations in pixels, see `convolve`
+pub fn gaussian_blur<T>(
+	src: &ImageDescriptor<T>,
+	dst: &mut ImageBufferMut<T>,
+	window: RectI,
+	sigma_x: Double,
+	sigma_y: Double,
+	edge: EdgeMode,
+) where
+	T: KernelFormat,
+{
+	let horizontal = Kernel1D::gaussian(sigma_x);
+	let vertical = Kernel1D::gaussian(sigma_y);
+	convolve(src, dst, window, &horizontal, &vertical, edge);
+}
+
+/// Recursive approximation of `gaussian_blur`, costing the same whatever
+/// the sigma. Reads as far as `gaussian_radius` like `gaussian_blur`;
+/// sigmas under half a pixel leave the image as it is.
+pub fn recursive_gaussian_blur<T>(
+	src: &ImageDescriptor<T>,
+	dst: &mut ImageBufferMut<T>,
+	window: RectI,
+	sigma_x: Double,
+	sigma_y: Double,
+	edge: EdgeMode,
+) where
+	T: KernelFormat,
+{
+	let (radius_x, radius_y) = (gaussian_radius(sigma_x), gaussian_radius(sigma_y));
+	let horizontal = Recursive::new(sigma_x);
+	let vertical = Recursive::new(sigma_y);
+	let filter = |recursive: &Option<Recursive>, radius, input: &[Colour], output: &mut [Colour]| {
+		match *recursive {
+			Some(ref recursive) => recursive.line(radius, input, output),
+			None => output.copy_from_slice(&input[radius..radius + output.len()]),
+		}
+	};
+	separable(
+		src,
+		dst,
+		window,
+		edge,
+		(radius_x, radius_y),
+		|input, output| filter(&horizontal, radius_x, input, output),
+		|input, output| filter(&vertical, radius_y, input, output),
+	);
+}
+
+/// Minimum of each channel over a `2 * radius + 1` rectangle, see `convolve`
+pub fn erode<T>(
+	src: &ImageDescriptor<T>,
+	dst: &mut ImageBufferMut<T>,
+	window: RectI,
+	radius_x: usize,
+	radius_y: usize,
+	edge: EdgeMode,
+) where
+	T: KernelFormat,
+{
+	separable(
+		src,
+		dst,
+		window,
+		edge,
+		(radius_x, radius_y),
+		|input, output| extremum_line(radius_x, f32::min, input, output),
+		|input, output| extremum_line(radius_y, f32::min, input, output),
+	);
+}
+
+/// Maximum of each channel over a `2 * radius + 1` rectangle, see `convolve`
+pub fn dilate<T>(
+	src: &ImageDescriptor<T>,
+	dst: &mut ImageBufferMut<T>,
+	window: RectI,
+	radius_x: usize,
+	radius_y: usize,
+	edge: EdgeMode,
+) where
+	T: KernelFormat,
+{
+	separable(
+		src,
+		dst,
+		window,
+		edge,
+		(radius_x, radius_y),
+		|input, output| extremum_line(radius_x, f32::max, input, output),
+		|input, output| extremum_line(radius_y, f32::max, input, output),
+	);
+}
+
+mod tests {
+	use super::*;
+
+	const BOUNDS: RectI = RectI {
+		x1: 0,
+		y1: 0,
+		x2: 7,
+		y2: 5,
+	};
+
+	// a single lit pixel in the middle of a 7 by 5 alpha image
+	fn impulse() -> Vec<f32> {
+		let mut pixels = vec![0.0; 35];
+		pixels[2 * 7 + 3] = 1.0;
+		pixels
+	}
+
+	fn filter<F>(src: &mut [f32], f: F) -> Vec<f32>
+	where
+		F: FnOnce(&ImageDescriptor<f32>, &mut ImageBufferMut<f32>),
+	{
+		let mut dst = vec![-1.0; 35];
+		let src = unsafe { ImageDescriptor::new(BOUNDS, 28, src.as_mut_ptr() as VoidPtrMut) };
+		let mut descriptor =
+			unsafe { ImageDescriptorMut::new(BOUNDS, 28, dst.as_mut_ptr() as VoidPtrMut) };
+		f(&src, &mut descriptor.data());
+		dst
+	}
+
+	fn assert_close(a: f32, b: f32) {
+		assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+	}
+
+	#[test]
+	fn box_blur_spreads_impulse() {
+		let dst = filter(&mut impulse(), |src, dst| {
+			box_blur(src, dst, BOUNDS, 1, 1, EdgeMode::Black)
+		});
+		assert_close(dst[7 + 2], 1.0 / 9.0);
+		assert_close(dst[3 * 7 + 4], 1.0 / 9.0);
+		assert_eq!(dst[0], 0.0);
+		assert_close(dst.iter().sum(), 1.0);
+	}
+
+	#[test]
+	fn window_is_clipped_and_the_rest_untouched() {
+		let window = RectI {
+			x1: 5,
+			y1: 4,
+			x2: 9,
+			y2: 9,
+		};
+		let dst = filter(&mut vec![0.5; 35], |src, dst| {
+			gaussian_blur(src, dst, window, 2.0, 2.0, EdgeMode::Clamp)
+		});
+		assert_close(dst[4 * 7 + 6], 0.5);
+		assert_eq!(dst[4 * 7 + 4], -1.0);
+		assert_eq!(dst[3 * 7 + 6], -1.0);
+	}
+
+	#[test]
+	fn gaussian_kernels() {
+		let kernel = Kernel1D::gaussian(1.0);
+		assert_eq!(kernel.radius(), 3);
+		assert_close(kernel.weights().iter().sum(), 1.0);
+		assert_eq!(Kernel1D::gaussian(0.0).weights(), &[1.0]);
+	}
+
+	#[test]
+	fn recursive_gaussian_is_close_to_gaussian() {
+		let mut src = impulse();
+		let exact = filter(&mut src, |src, dst| {
+			gaussian_blur(src, dst, BOUNDS, 1.5, 1.5, EdgeMode::Black)
+		});
+		let recursive = filter(&mut src, |src, dst| {
+			recursive_gaussian_blur(src, dst, BOUNDS, 1.5, 1.5, EdgeMode::Black)
+		});
+		for (exact, recursive) in exact.iter().zip(&recursive) {
+			assert!((exact - recursive).abs() < 0.01, "{} != {}", exact, recursive);
+		}
+		// a flat image stays flat
+		let flat = filter(&mut vec![0.25; 35], |src, dst| {
+			recursive_gaussian_blur(src, dst, BOUNDS, 4.0, 4.0, EdgeMode::Clamp)
+		});
+		for value in flat {
+			assert_close(value, 0.25);
+		}
+	}
+
+	#[test]
+	fn morphology() {
+		let dilated = filter(&mut impulse(), |src, dst| {
+			dilate(src, dst, BOUNDS, 2, 1, EdgeMode::Black)
+		});
+		assert_eq!(dilated[7 + 1], 1.0);
+		assert_eq!(dilated[3 * 7 + 5], 1.0);
+		assert_eq!(dilated[7], 0.0);
+		assert_eq!(dilated[4 * 7 + 3], 0.0);
+		let mut src = vec![1.0; 35];
+		src[0] = 0.0;
+		let eroded = filter(&mut src, |src, dst| erode(src, dst, BOUNDS, 1, 1, EdgeMode::Clamp));
+		assert_eq!((eroded[7 + 1], eroded[7 + 2]), (0.0, 1.0));
+	}
+
+	#[test]
+	fn convolve_2d_matches_separable() {
+		let mut src = impulse();
+		let weights = [1.0, 2.0, 1.0];
+		let kernel = Kernel2D::new(
+			3,
+			3,
+			weights.iter().flat_map(|a| weights.iter().map(move |b| a * b / 16.0)).collect(),
+		);
+		let full = filter(&mut src, |src, dst| {
+			convolve_2d(src, dst, BOUNDS, &kernel, EdgeMode::Mirror)
+		});
+		let kernel = Kernel1D::new(weights.iter().map(|weight| weight / 4.0).collect());
+		let separated = filter(&mut src, |src, dst| {
+			convolve(src, dst, BOUNDS, &kernel, &kernel, EdgeMode::Mirror)
+		});
+		for (full, separated) in full.iter().zip(&separated) {
+			assert_close(*full, *separated);
+		}
+		assert_close(full[2 * 7 + 3], 0.25);
+	}
+
+	#[test]
+	fn empty_sources_read_black() {
+		let empty = RectI { y2: 0, ..BOUNDS };
+		let mut pixels = [0.5f32; 1];
+		let src: ImageDescriptor<f32> = unsafe { ImageDescriptor::new(empty, 28, pixels.as_mut_ptr() as VoidPtrMut) };
+		let mut dst = vec![-1.0f32; 35];
+		let mut descriptor =
+			unsafe { ImageDescriptorMut::new(BOUNDS, 28, dst.as_mut_ptr() as VoidPtrMut) };
+		for edge in &[EdgeMode::Repeat, EdgeMode::Mirror, EdgeMode::Clamp] {
+			box_blur(&src, &mut descriptor.data(), BOUNDS, 1, 1, *edge);
+			assert!(dst.iter().all(|&value| value == 0.0));
+		}
+	}
+
+	#[test]
+	fn kernels_are_flipped() {
+		let mut src = impulse();
+		let horizontal = Kernel1D::new(vec![1.0, 2.0, 3.0]);
+		let vertical = Kernel1D::new(vec![1.0]);
+		let dst = filter(&mut src, |src, dst| {
+			convolve(src, dst, BOUNDS, &horizontal, &vertical, EdgeMode::Black)
+		});
+		assert_eq!(&dst[2 * 7 + 2..2 * 7 + 5], &[1.0, 2.0, 3.0]);
+
+		let kernel = Kernel2D::new(3, 3, (1..10).map(|weight| weight as f32).collect());
+		let dst = filter(&mut src, |src, dst| {
+			convolve_2d(src, dst, BOUNDS, &kernel, EdgeMode::Black)
+		});
+		for row in 0..3 {
+			let start = (1 + row) * 7 + 2;
+			assert_eq!(&dst[start..start + 3], &kernel.weights[row * 3..row * 3 + 3]);
+		}
+	}
+
+	#[test]
+	fn roi_grows_by_radius_in_canonical_units() {
+		let coordinates = CoordinateSystem::new(PointD { x: 0.5, y: 0.5 }, 2.0);
+		let roi = RectD {
+			x1: 0.0,
+			y1: 0.0,
+			x2: 100.0,
+			y2: 50.0,
+		};
+		let grown = region_of_interest(roi, 3, 3, &coordinates);
+		assert_eq!(
+			(grown.x1, grown.y1, grown.x2, grown.y2),
+			(-12.0, -6.0, 112.0, 56.0)
+		);
+	}
+}
@@ -0,0 +1,213 @@
+use handle::*;
+use property::*;
+use result::*;
+use types::*;
+
+/// Converts between the coordinate spaces of an effect:
+///
+/// * canonical, the project's space, independent of render scale and pixel
+///   aspect ratio, used by regions of definition and of interest;
+/// * pixel, the space of image bounds and render windows, where
+///   `pixel.x = canonical.x * render_scale.x / pixel_aspect_ratio` and
+///   `pixel.y = canonical.y * render_scale.y`;
+/// * normalised, where the project spans `0..1`.
+///
+/// Without a project, normalised coordinates are the canonical ones.
+#[derive(Debug, Clone, Copy)]
+pub struct CoordinateSystem {
+	render_scale: PointD,
+	pixel_aspect_ratio: Double,
+	project_offset: PointD,
+	project_size: PointD,
+}
+
+impl Default for CoordinateSystem {
+	fn default() -> Self {
+		CoordinateSystem::new(PointD { x: 1.0, y: 1.0 }, 1.0)
+	}
+}
+
+impl CoordinateSystem {
+	pub fn new(render_scale: PointD, pixel_aspect_ratio: Double) -> Self {
+		CoordinateSystem {
+			render_scale,
+			pixel_aspect_ratio,
+			project_offset: PointD { x: 0.0, y: 0.0 },
+			project_size: PointD { x: 1.0, y: 1.0 },
+		}
+	}
+
+	/// The render scale and pixel aspect ratio an image was rendered with
+	pub fn from_image(image: &Image) -> Result<Self> {
+		Ok(CoordinateSystem::new(
+			image.get_render_scale()?,
+			image.get_pixel_aspect_ratio()?,
+		))
+	}
+
+	/// The render scale of a render call, for images of `clip`
+	pub fn from_render(args: &RenderInArgs, clip: &ClipInstance) -> Result<Self> {
+		Ok(CoordinateSystem::new(
+			args.get_render_scale()?,
+			clip.get_pixel_aspect_ratio()?,
+		))
+	}
+
+	/// Normalised coordinates relative to a project of `size` at `offset`
+	pub fn project(self, offset: PointD, size: PointD) -> Self {
+		CoordinateSystem {
+			project_offset: offset,
+			project_size: size,
+			..self
+		}
+	}
+
+	/// Normalised coordinates relative to the effect's project
+	pub fn project_of(self, effect: &ImageEffectHandle) -> Result<Self> {
+		let properties: EffectInstance = effect.properties()?;
+		Ok(self.project(properties.get_project_offset()?, properties.get_project_size()?))
+	}
+
+	pub fn render_scale(&self) -> PointD {
+		self.render_scale
+	}
+
+	pub fn pixel_aspect_ratio(&self) -> Double {
+		self.pixel_aspect_ratio
+	}
+
+	// canonical to pixel scale factors
+	fn scale(&self) -> (Double, Double) {
+		(
+			self.render_scale.x / self.pixel_aspect_ratio,
+			self.render_scale.y,
+		)
+	}
+
+	pub fn canonical_to_pixel(&self, point: PointD) -> PointD {
+		let (sx, sy) = self.scale();
+		PointD {
+			x: point.x * sx,
+			y: point.y * sy,
+		}
+	}
+
+	pub fn pixel_to_canonical(&self, point: PointD) -> PointD {
+		let (sx, sy) = self.scale();
+		PointD {
+			x: point.x / sx,
+			y: point.y / sy,
+		}
+	}
+
+	pub fn canonical_to_normalised(&self, point: PointD) -> PointD {
+		PointD {
+			x: (point.x - self.project_offset.x) / self.project_size.x,
+			y: (point.y - self.project_offset.y) / self.project_size.y,
+		}
+	}
+
+	pub fn normalised_to_canonical(&self, point: PointD) -> PointD {
+		PointD {
+			x: self.project_offset.x + point.x * self.project_size.x,
+			y: self.project_offset.y + point.y * self.project_size.y,
+		}
+	}
+
+	/// The smallest pixel rect covering `rect`, rounding outwards
+	pub fn canonical_rect_to_pixel(&self, rect: RectD) -> RectI {
+		let (sx, sy) = self.scale();
+		RectI {
+			x1: (rect.x1 * sx).floor() as Int,
+			y1: (rect.y1 * sy).floor() as Int,
+			x2: (rect.x2 * sx).ceil() as Int,
+			y2: (rect.y2 * sy).ceil() as Int,
+		}
+	}
+
+	pub fn pixel_rect_to_canonical(&self, rect: RectI) -> RectD {
+		let (sx, sy) = self.scale();
+		RectD {
+			x1: Double::from(rect.x1) / sx,
+			y1: Double::from(rect.y1) / sy,
+			x2: Double::from(rect.x2) / sx,
+			y2: Double::from(rect.y2) / sy,
+		}
+	}
+
+	pub fn canonical_rect_to_normalised(&self, rect: RectD) -> RectD {
+		let min = self.canonical_to_normalised(PointD { x: rect.x1, y: rect.y1 });
+		let max = self.canonical_to_normalised(PointD { x: rect.x2, y: rect.y2 });
+		RectD {
+			x1: min.x,
+			y1: min.y,
+			x2: max.x,
+			y2: max.y,
+		}
+	}
+
+	pub fn normalised_rect_to_canonical(&self, rect: RectD) -> RectD {
+		let min = self.normalised_to_canonical(PointD { x: rect.x1, y: rect.y1 });
+		let max = self.normalised_to_canonical(PointD { x: rect.x2, y: rect.y2 });
+		RectD {
+			x1: min.x,
+			y1: min.y,
+			x2: max.x,
+			y2: max.y,
+		}
+	}
+}
+
+mod tests {
+	use super::*;
+
+	fn point(x: Double, y: Double) -> PointD {
+		PointD { x, y }
+	}
+
+	// half resolution proxy of anamorphic footage
+	fn proxy() -> CoordinateSystem {
+		CoordinateSystem::new(point(0.5, 0.5), 2.0)
+			.project(point(-100.0, 0.0), point(1000.0, 500.0))
+	}
+
+	#[test]
+	fn points_round_trip() {
+		let system = proxy();
+		let pixel = system.canonical_to_pixel(point(400.0, 100.0));
+		assert_eq!((pixel.x, pixel.y), (100.0, 50.0));
+		let canonical = system.pixel_to_canonical(pixel);
+		assert_eq!((canonical.x, canonical.y), (400.0, 100.0));
+		let normalised = system.canonical_to_normalised(canonical);
+		assert_eq!((normalised.x, normalised.y), (0.5, 0.2));
+		let canonical = system.normalised_to_canonical(normalised);
+		assert_eq!((canonical.x, canonical.y), (400.0, 100.0));
+	}
+
+	#[test]
+	fn pixel_rects_round_outwards() {
+		let rect = proxy().canonical_rect_to_pixel(RectD {
+			x1: -3.0,
+			y1: 1.0,
+			x2: 5.0,
+			y2: 3.5,
+		});
+		assert_eq!((rect.x1, rect.y1, rect.x2, rect.y2), (-1, 0, 2, 2));
+		let back = proxy().pixel_rect_to_canonical(rect);
+		assert_eq!((back.x1, back.y1, back.x2, back.y2), (-4.0, 0.0, 8.0, 4.0));
+	}
+
+	#[test]
+	fn unit_project_is_canonical() {
+		let normalised = CoordinateSystem::default().canonical_rect_to_normalised(RectD {
+			x1: 1.0,
+			y1: 2.0,
+			x2: 3.0,
+			y2: 4.0,
+		});
+		assert_eq!(
+			(normalised.x1, normalised.y1, normalised.x2, normalised.y2),
+			(1.0, 2.0, 3.0, 4.0)
+		);
+	}
+}
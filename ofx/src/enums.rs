@@ -1,5 +1,7 @@
 use ofx_sys::*;
+use result::*;
 use std::ffi::CStr;
+use types::*;
 
 pub trait IdentifiedEnum: Sized {
 	fn to_bytes(&self) -> &'static [u8];
@@ -19,7 +21,7 @@ macro_rules! identified_enum {
 		*
 	}) =>
 	{
-		#[derive(Copy, Clone, Debug)]
+		#[derive(Copy, Clone, Debug, PartialEq)]
 		$visibility enum $name {
 			$($key),
 			*
@@ -68,6 +70,155 @@ macro_rules! identified_enum {
 	}
 }
 
+/// A single option of a choice parameter backed by a Rust enum.
+///
+/// `index` is the value the host stores for the option and must never change once
+/// a plugin has shipped; `name` is the value stored by string-valued choices.
+#[derive(Copy, Clone, Debug)]
+pub struct ChoiceOption<T: 'static> {
+	pub value: T,
+	pub index: Int,
+	pub name: &'static str,
+	pub label: &'static str,
+	pub hint: &'static str,
+}
+
+/// Display order and option at a stored index, `None` for a retired index
+pub type ChoiceSlot<E> = (Int, Option<&'static ChoiceOption<E>>);
+
+/// Rust enums that can be used as the value of a choice parameter.
+///
+/// Implemented by the `choice_enum!` macro. Options are listed in display order,
+/// the stored value of each option is its `index`, so options can be reordered in
+/// later plugin versions without breaking saved projects, as long as the indices
+/// are preserved. Options can be retired by leaving their index unused.
+/// Choices Natron lets users add options to are matched by label instead.
+pub trait ChoiceEnum: Copy + PartialEq + Default + 'static {
+	fn options() -> &'static [ChoiceOption<Self>];
+
+	fn option(self) -> &'static ChoiceOption<Self> {
+		Self::options()
+			.iter()
+			.find(|option| option.value == self)
+			.expect("Choice option missing from its own enum, this is a bug")
+	}
+
+	fn index(self) -> Int {
+		self.option().index
+	}
+
+	fn name(self) -> &'static str {
+		self.option().name
+	}
+
+	fn label(self) -> &'static str {
+		self.option().label
+	}
+
+	fn from_index(index: Int) -> Option<Self> {
+		Self::options()
+			.iter()
+			.find(|option| option.index == index)
+			.map(|option| option.value)
+	}
+
+	fn from_name(name: &str) -> Option<Self> {
+		Self::options()
+			.iter()
+			.find(|option| option.name == name)
+			.map(|option| option.value)
+	}
+
+	fn from_label(label: &str) -> Option<Self> {
+		Self::options()
+			.iter()
+			.find(|option| option.label == label)
+			.map(|option| option.value)
+	}
+
+	/// The host's option list, one entry per stored index from 0 to the largest
+	/// one, paired with its display order. Indices left unused by retired
+	/// options are `None` and ordered after the others. Fails on negative or
+	/// repeated indices, since OFX choice values are positions in the option list.
+	fn options_by_index() -> Result<Vec<ChoiceSlot<Self>>> {
+		let options = Self::options();
+		let size = options
+			.iter()
+			.map(|option| option.index + 1)
+			.max()
+			.unwrap_or(0);
+		let mut by_index = vec![(0, None); size.max(0) as usize];
+		for (order, option) in options.iter().enumerate() {
+			match by_index.get_mut(option.index as usize) {
+				Some(entry) if option.index >= 0 && entry.1.is_none() => {
+					*entry = (order as Int, Some(option))
+				}
+				_ => {
+					error!(
+						"Choice option {} has a bad index {}",
+						option.name, option.index
+					);
+					return Err(Error::InvalidValue);
+				}
+			}
+		}
+		let retired = by_index.iter_mut().filter(|entry| entry.1.is_none());
+		for (order, entry) in (options.len() as Int..).zip(retired) {
+			entry.0 = order;
+		}
+		Ok(by_index)
+	}
+}
+
+/// Declares an enum usable as a choice parameter value.
+///
+/// ```ignore
+/// choice_enum! {
+///     pub enum Filter {
+///         Nearest = 0 => "nearest", "Nearest", "No filtering";
+///         Bilinear = 1 => "bilinear", "Bilinear", "Linear interpolation";
+///     }
+/// }
+/// ```
+///
+/// Each variant lists its stored index, option name, label and hint.
+/// Declaration order is the display order, the first variant is the default.
+#[macro_export]
+macro_rules! choice_enum {
+	($visibility:vis enum $name:ident {
+		$($key:ident = $index:expr => $option_name:expr, $label:expr, $hint:expr);
+		+ $(;)*
+	}) => {
+		#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+		$visibility enum $name {
+			$($key),
+			+
+		}
+
+		impl Default for $name {
+			fn default() -> Self {
+				<$name as $crate::ChoiceEnum>::options()[0].value
+			}
+		}
+
+		impl $crate::ChoiceEnum for $name {
+			fn options() -> &'static [$crate::ChoiceOption<Self>] {
+				const OPTIONS: &[$crate::ChoiceOption<$name>] = &[
+					$($crate::ChoiceOption {
+						value: $name::$key,
+						index: $index,
+						name: $option_name,
+						label: $label,
+						hint: $hint,
+					}),
+					+
+				];
+				OPTIONS
+			}
+		}
+	};
+}
+
 identified_enum! {
 	pub enum Type {
 		ImageEffectHost,
@@ -123,7 +274,8 @@ identified_enum! {
 	pub enum ImageComponent {
 		RGBA,
 		RGB,
-		Alpha
+		Alpha,
+		YUVA
 	}
 }
 
@@ -133,7 +285,11 @@ impl ImageComponent {
 	}
 
 	pub fn is_rgb(self) -> bool {
-		!self.is_alpha()
+		self == ImageComponent::RGBA || self == ImageComponent::RGB
+	}
+
+	pub fn is_yuv(self) -> bool {
+		self == ImageComponent::YUVA
 	}
 }
 
@@ -153,7 +309,8 @@ identified_enum! {
 		Custom,
 		Group,
 		Page,
-		PushButton
+		PushButton,
+		StrChoice
 	}
 }
 
@@ -241,6 +398,17 @@ identified_enum! {
 	}
 }
 
+identified_enum! {
+	pub enum MessageType {
+		Fatal => kOfxMessageFatal,
+		Error => kOfxMessageError,
+		Warning => kOfxMessageWarning,
+		Message => kOfxMessageMessage,
+		Log => kOfxMessageLog,
+		Question => kOfxMessageQuestion
+	}
+}
+
 identified_enum! {
 	pub enum ParamInvalidate {
 		All,
@@ -250,6 +418,61 @@ identified_enum! {
 
 mod tests {
 	use super::*;
+
+	choice_enum! {
+		enum Quality {
+			Draft = 2 => "draft", "Draft", "Fastest";
+			Normal = 0 => "normal", "Normal", "Balanced";
+			Best = 1 => "best", "Best", "Slowest";
+		}
+	}
+
+	choice_enum! {
+		enum Retired {
+			First = 0 => "first", "First", "";
+			Third = 2 => "third", "Third", "";
+		}
+	}
+
+	choice_enum! {
+		enum Broken {
+			First = 0 => "first", "First", "";
+			Second = 0 => "second", "Second", "";
+		}
+	}
+
+	#[test]
+	fn choice_enum_indices() {
+		assert!(Quality::default() == Quality::Draft);
+		assert!(Quality::Best.index() == 1);
+		assert!(Quality::from_index(0) == Some(Quality::Normal));
+		assert!(Quality::from_index(3) == None);
+		assert!(Quality::from_name("draft") == Some(Quality::Draft));
+		assert!(Quality::from_label("Best") == Some(Quality::Best));
+		assert!(Quality::from_label("best") == None);
+		assert!(Quality::Normal.label() == "Normal");
+
+		let by_index = Quality::options_by_index().unwrap();
+		let names: Vec<_> = by_index
+			.iter()
+			.map(|(_, option)| option.map(|option| option.name))
+			.collect();
+		let orders: Vec<_> = by_index.iter().map(|(order, _)| *order).collect();
+		assert!(names == [Some("normal"), Some("best"), Some("draft")]);
+		assert!(orders == [1, 2, 0]);
+
+		let by_index = Retired::options_by_index().unwrap();
+		let names: Vec<_> = by_index
+			.iter()
+			.map(|(_, option)| option.map(|option| option.name))
+			.collect();
+		let orders: Vec<_> = by_index.iter().map(|(order, _)| *order).collect();
+		assert!(names == [Some("first"), None, Some("third")]);
+		assert!(orders == [0, 2, 1]);
+		assert!(Retired::from_index(1) == None);
+
+		assert!(Broken::options_by_index().is_err());
+	}
 	#[test]
 	fn auto_enum_names() {
 		assert!(ImageEffectContext::Filter.to_bytes() == kOfxImageEffectContextFilter);
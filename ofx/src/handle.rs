@@ -1,5 +1,8 @@
 use enums::*;
 use image::*;
+use logger::set_log_context;
+use memory::ImageMemory;
+use path::FileMode;
 use ofx_sys::*;
 use property::*;
 use result::*;
@@ -9,6 +12,7 @@ use std::ffi::{CStr, CString};
 use std::fmt;
 use std::marker::PhantomData;
 use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use types::*;
 
@@ -54,6 +58,36 @@ impl ImageEffectHost {
 	}
 }
 
+/// Asks the host whether the render of one instance should stop, from any
+/// thread. Obtained with `ImageEffectHandle::abort_poll`.
+#[derive(Clone, Copy)]
+pub struct AbortPoll {
+	abort: unsafe extern "C" fn(OfxImageEffectHandle) -> std::os::raw::c_int,
+	inner: OfxImageEffectHandle,
+}
+
+// the host's abort function is safe to call from render threads
+unsafe impl Send for AbortPoll {}
+unsafe impl Sync for AbortPoll {}
+
+impl AbortPoll {
+	pub(crate) fn new(
+		abort: unsafe extern "C" fn(OfxImageEffectHandle) -> std::os::raw::c_int,
+		inner: OfxImageEffectHandle,
+	) -> Self {
+		AbortPoll { abort, inner }
+	}
+
+	/// Fails with `Error::Aborted` once the host has asked to stop
+	pub fn check(&self) -> Result<()> {
+		if unsafe { (self.abort)(self.inner) } != 0 {
+			Err(Error::Aborted)
+		} else {
+			Ok(())
+		}
+	}
+}
+
 #[derive(Clone)]
 pub struct ImageEffectHandle {
 	inner: OfxImageEffectHandle,
@@ -61,8 +95,91 @@ pub struct ImageEffectHandle {
 	image_effect: Rc<OfxImageEffectSuiteV1>,
 	image_effect_opengl_render: Option<Rc<OfxImageEffectOpenGLRenderSuiteV1>>,
 	parameter: Rc<OfxParameterSuiteV1>,
+	message: Rc<OfxMessageSuiteV1>,
+	message_v2: Option<Rc<OfxMessageSuiteV2>>,
+	progress: Rc<OfxProgressSuiteV1>,
+	progress_v2: Option<Rc<OfxProgressSuiteV2>>,
+	time_line: Rc<OfxTimeLineSuiteV1>,
+}
+
+/// A progress bar shown by the host, see `ImageEffectHandle::progress`.
+pub struct Progress {
+	effect: ImageEffectHandle,
+	active: bool,
+}
+
+impl Progress {
+	/// Sets the progress to `fraction` between 0 and 1. Fails with
+	/// `Error::Aborted` once the user has asked to stop.
+	pub fn update(&self, fraction: Double) -> Result<()> {
+		let handle = self.effect.inner as *mut std::os::raw::c_void;
+		let status = match self.effect.progress_v2 {
+			Some(ref progress_v2) => suite_call!(progressUpdate in progress_v2; handle, fraction),
+			None => suite_call!(progressUpdate in self.effect.progress; handle, fraction),
+		};
+		progress_result(status)
+	}
+
+	pub fn end(mut self) -> Result<()> {
+		self.active = false;
+		self.progress_end()
+	}
+
+	fn progress_end(&self) -> Result<()> {
+		let handle = self.effect.inner as *mut std::os::raw::c_void;
+		match self.effect.progress_v2 {
+			Some(ref progress_v2) => suite_fn!(progressEnd in progress_v2; handle),
+			None => suite_fn!(progressEnd in self.effect.progress; handle),
+		}
+	}
+}
+
+// the host answers an update with ReplyNo once the user cancelled
+fn progress_result(status: OfxStatus) -> Result<()> {
+	match status {
+		ofx_sys::eOfxStatus_OK => Ok(()),
+		ofx_sys::eOfxStatus_ReplyNo => Err(Error::Aborted),
+		other => Err(Error::from(other)),
+	}
+}
+
+impl Drop for Progress {
+	fn drop(&mut self) {
+		if self.active {
+			self.progress_end().ok();
+		}
+	}
+}
+
+/// Answer to `ImageEffectHandle::question`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageReply {
+	Yes,
+	No,
+}
+
+impl MessageReply {
+	fn from_status(status: OfxStatus) -> Result<MessageReply> {
+		match status {
+			ofx_sys::eOfxStatus_ReplyYes => Ok(MessageReply::Yes),
+			ofx_sys::eOfxStatus_ReplyNo => Ok(MessageReply::No),
+			other => Err(Error::from(other)),
+		}
+	}
 }
 
+// plain messages are acknowledged with either OK or ReplyDefault
+fn message_result(status: OfxStatus) -> Result<()> {
+	if status == eOfxStatus_OK || status == eOfxStatus_ReplyDefault {
+		Ok(())
+	} else {
+		Err(Error::from(status))
+	}
+}
+
+// messages are passed as a printf argument so that '%' in them is harmless
+pub(crate) const MESSAGE_FORMAT: &[u8] = b"%s\0";
+
 #[derive(Clone)]
 pub struct ClipInstance {
 	inner: OfxImageClipHandle,
@@ -72,7 +189,8 @@ pub struct ClipInstance {
 	image_effect_opengl_render: Option<Rc<OfxImageEffectOpenGLRenderSuiteV1>>,
 }
 
-#[derive(Clone)]
+// not Clone: a copy would release the host image twice and could hand out
+// mutable descriptors aliasing the same pixels
 pub struct Image {
 	inner: OfxPropertySetHandle,
 	property: Rc<OfxPropertySuiteV1>,
@@ -81,11 +199,23 @@ pub struct Image {
 	is_texture: bool
 }
 
-pub trait ParamHandleValue: Default + Clone {}
+pub trait ParamHandleValue: Default + Clone {
+	// choices may be stored as an index, a name or a label, checked once on fetch
+	#[doc(hidden)]
+	const IS_CHOICE: bool = false;
+}
 impl ParamHandleValue for Int {}
 impl ParamHandleValue for Bool {}
 impl ParamHandleValue for Double {}
 impl ParamHandleValue for String {}
+impl ParamHandleValue for PathBuf {}
+
+impl<E> ParamHandleValue for E
+where
+	E: ChoiceEnum,
+{
+	const IS_CHOICE: bool = true;
+}
 
 pub trait ParamHandleValueDefault: ParamHandleValue + Default {}
 impl ParamHandleValueDefault for Int {}
@@ -100,9 +230,30 @@ where
 	inner_properties: OfxPropertySetHandle,
 	property: Rc<OfxPropertySuiteV1>,
 	parameter: Rc<OfxParameterSuiteV1>,
+	choice: ChoiceStorage,
 	_type: PhantomData<T>,
 }
 
+// How a typed choice handle finds its option in the host value
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ChoiceStorage {
+	Index,
+	// OFX 1.5 StrChoice, the value is the option's name
+	Name,
+	// Natron choice the host can add options to, the value is an index
+	// but only the option string at it identifies the choice
+	Label,
+}
+
+/// A set of parameters defined and fetched together, usually implemented
+/// with `#[derive(OfxParams)]` on a struct holding the parameter values.
+pub trait OfxParams: Sized {
+	type Handles;
+
+	fn define(param_set: &mut ParamSetHandle) -> Result<()>;
+	fn fetch(param_set: &ParamSetHandle) -> Result<Self::Handles>;
+}
+
 #[derive(Clone)]
 pub struct ParamSetHandle {
 	inner: OfxParamSetHandle,
@@ -110,6 +261,28 @@ pub struct ParamSetHandle {
 	parameter: Rc<OfxParameterSuiteV1>,
 }
 
+/// Groups parameter changes into one undo step, see `ParamSetHandle::edit`.
+pub struct ParamEdit {
+	param_set: ParamSetHandle,
+	active: bool,
+}
+
+impl ParamEdit {
+	/// Ends the edit block, reporting any error from the host.
+	pub fn end(mut self) -> Result<()> {
+		self.active = false;
+		self.param_set.edit_end()
+	}
+}
+
+impl Drop for ParamEdit {
+	fn drop(&mut self) {
+		if self.active {
+			self.param_set.edit_end().ok();
+		}
+	}
+}
+
 // TODO: custom_derive?
 macro_rules! trivial_debug {
 	($($struct:ty),*) => {
@@ -124,18 +297,26 @@ macro_rules! trivial_debug {
 
 trivial_debug!(
 	ClipInstance,
+	ParamEdit,
+	Progress,
 	ImageEffectHandle,
 	GenericPluginHandle,
 	ImageEffectHost
 );
 
 impl ImageEffectHandle {
+	#[allow(clippy::too_many_arguments)]
 	pub fn new(
 		inner: OfxImageEffectHandle,
 		property: Rc<OfxPropertySuiteV1>,
 		image_effect: Rc<OfxImageEffectSuiteV1>,
 		image_effect_opengl_render: Option<Rc<OfxImageEffectOpenGLRenderSuiteV1>>,
 		parameter: Rc<OfxParameterSuiteV1>,
+		message: Rc<OfxMessageSuiteV1>,
+		message_v2: Option<Rc<OfxMessageSuiteV2>>,
+		progress: Rc<OfxProgressSuiteV1>,
+		progress_v2: Option<Rc<OfxProgressSuiteV2>>,
+		time_line: Rc<OfxTimeLineSuiteV1>,
 	) -> Self {
 		ImageEffectHandle {
 			inner,
@@ -143,6 +324,11 @@ impl ImageEffectHandle {
 			image_effect,
 			image_effect_opengl_render,
 			parameter,
+			message,
+			message_v2,
+			progress,
+			progress_v2,
+			time_line,
 		}
 	}
 }
@@ -162,6 +348,7 @@ where
 			inner_properties,
 			property,
 			parameter,
+			choice: ChoiceStorage::Index,
 			_type: PhantomData,
 		}
 	}
@@ -218,6 +405,42 @@ where
 	}
 }
 
+impl<T> ParamHandle<T>
+where
+	T: ParamHandleValue,
+{
+	/// Copies the value and animation of `from` into this parameter, shifting
+	/// keys by `offset`. When `range` is given only keys inside it are copied.
+	/// Both parameters must be of the same type.
+	pub fn copy_from(
+		&self,
+		from: &ParamHandle<T>,
+		offset: Time,
+		range: Option<RangeD>,
+	) -> Result<()> {
+		let range_ptr = range
+			.as_ref()
+			.map(|range| range as *const RangeD)
+			.unwrap_or(std::ptr::null());
+		suite_fn!(paramCopy in self.parameter; self.inner, from.inner, offset, range_ptr)
+	}
+}
+
+impl ParamHandle<Double> {
+	pub fn get_derivative(&self, time: Time) -> Result<Double> {
+		let mut value: Double = 0.0;
+		suite_fn!(paramGetDerivative in self.parameter; self.inner, time, &mut value as *mut Double)?;
+		Ok(value)
+	}
+
+	/// Integral of the parameter's curve between `time1` and `time2`.
+	pub fn get_integral(&self, time1: Time, time2: Time) -> Result<Double> {
+		let mut value: Double = 0.0;
+		suite_fn!(paramGetIntegral in self.parameter; self.inner, time1, time2, &mut value as *mut Double)?;
+		Ok(value)
+	}
+}
+
 impl ParamHandle<Bool> {
 	pub fn get_value(&self) -> Result<Bool> {
 		let mut value: Int = 0;
@@ -276,6 +499,147 @@ impl ParamHandle<String> {
 	}
 }
 
+impl ParamHandle<PathBuf> {
+	fn as_string(&self) -> ParamHandle<String> {
+		ParamHandle::new(
+			self.inner,
+			self.inner_properties,
+			self.property.clone(),
+			self.parameter.clone(),
+		)
+	}
+
+	pub fn get_value(&self) -> Result<PathBuf> {
+		self.as_string().get_value().map(PathBuf::from)
+	}
+
+	pub fn get_value_at_time(&self, time: Time) -> Result<PathBuf> {
+		self.as_string().get_value_at_time(time).map(PathBuf::from)
+	}
+
+	pub fn set_value(&self, value: &Path) -> Result<()> {
+		self.as_string().set_value(path_to_string(value)?)
+	}
+
+	pub fn set_value_at_time(&self, time: Time, value: &Path) -> Result<()> {
+		self.as_string().set_value_at_time(time, path_to_string(value)?)
+	}
+}
+
+// position of our option among the host's, which may include options users added
+fn label_position(labels: &[String], label: &str) -> Option<Int> {
+	labels.iter().position(|host| host == label).map(|index| index as Int)
+}
+
+fn path_to_string(path: &Path) -> Result<String> {
+	path.to_str()
+		.map(|path| path.to_owned())
+		.ok_or(Error::InvalidNameEncoding)
+}
+
+impl<E> ParamHandle<E>
+where
+	E: ChoiceEnum,
+{
+	fn choice_from_index(index: Int) -> Result<E> {
+		E::from_index(index).ok_or_else(|| {
+			error!("No choice option with index {}", index);
+			Error::InvalidValue
+		})
+	}
+
+	fn choice_from_label(&self, index: Int) -> Result<E> {
+		if index < 0 {
+			return Self::choice_from_index(index);
+		}
+		let label = self.get_at::<choice::ChoiceOption>(index as usize)?;
+		E::from_label(&label).ok_or_else(|| {
+			error!("No choice option labelled {}", label);
+			Error::InvalidValue
+		})
+	}
+
+	// the host may have reordered the options, or added some before ours
+	fn label_index(&self, value: E) -> Result<Int> {
+		let labels = self.get_all::<choice::ChoiceOption>()?;
+		label_position(&labels, value.label()).ok_or_else(|| {
+			error!("Choice option {} missing from the host options", value.label());
+			Error::InvalidValue
+		})
+	}
+
+	fn choice_from_name(name: CharPtr) -> Result<E> {
+		if name.is_null() {
+			return Err(Error::InvalidValue);
+		}
+		let name = unsafe { CStr::from_ptr(name) }.to_str()?;
+		E::from_name(name).ok_or_else(|| {
+			error!("No choice option named {}", name);
+			Error::InvalidValue
+		})
+	}
+
+	pub fn get_choice(&self) -> Result<E> {
+		if self.choice == ChoiceStorage::Name {
+			let mut value: CharPtr = std::ptr::null();
+			suite_fn!(paramGetValue in self.parameter; self.inner, &mut value as *mut CharPtr)?;
+			Self::choice_from_name(value)
+		} else {
+			let mut value: Int = 0;
+			suite_fn!(paramGetValue in self.parameter; self.inner, &mut value as *mut Int)?;
+			self.choice_from_value(value)
+		}
+	}
+
+	pub fn get_choice_at_time(&self, time: Time) -> Result<E> {
+		if self.choice == ChoiceStorage::Name {
+			let mut value: CharPtr = std::ptr::null();
+			suite_fn!(paramGetValueAtTime in self.parameter; self.inner, time, &mut value as *mut CharPtr)?;
+			Self::choice_from_name(value)
+		} else {
+			let mut value: Int = 0;
+			suite_fn!(paramGetValueAtTime in self.parameter; self.inner, time, &mut value as *mut Int)?;
+			self.choice_from_value(value)
+		}
+	}
+
+	pub fn set_choice(&self, value: E) -> Result<()> {
+		match self.choice {
+			ChoiceStorage::Name => {
+				let name = CString::new(value.name())?;
+				suite_fn!(paramSetValue in self.parameter; self.inner, name.as_ptr())
+			}
+			ChoiceStorage::Label => {
+				suite_fn!(paramSetValue in self.parameter; self.inner, self.label_index(value)?)
+			}
+			ChoiceStorage::Index => suite_fn!(paramSetValue in self.parameter; self.inner, value.index()),
+		}
+	}
+
+	pub fn set_choice_at_time(&self, time: Time, value: E) -> Result<()> {
+		match self.choice {
+			ChoiceStorage::Name => {
+				let name = CString::new(value.name())?;
+				suite_fn!(paramSetValueAtTime in self.parameter; self.inner, time, name.as_ptr())
+			}
+			ChoiceStorage::Label => {
+				suite_fn!(paramSetValueAtTime in self.parameter; self.inner, time, self.label_index(value)?)
+			}
+			ChoiceStorage::Index => {
+				suite_fn!(paramSetValueAtTime in self.parameter; self.inner, time, value.index())
+			}
+		}
+	}
+
+	fn choice_from_value(&self, value: Int) -> Result<E> {
+		if self.choice == ChoiceStorage::Label {
+			self.choice_from_label(value)
+		} else {
+			Self::choice_from_index(value)
+		}
+	}
+}
+
 impl ClipInstance {
 	pub fn new(
 		inner: OfxImageClipHandle,
@@ -412,37 +776,56 @@ impl Image {
 		}
 	}
 
-	pub fn get_descriptor<T>(&self) -> Result<ImageDescriptor<T>>
+	// reading pixels as the wrong type would run past the rows
+	fn check_pixel_format<T>(&self) -> Result<()>
 	where
 		T: PixelFormat,
 	{
+		// textures have no pixel data on the CPU, whatever depth they report
+		if self.is_texture {
+			return Err(Error::Unsupported);
+		}
+		if self.get_pixel_depth()? == T::bit_depth() && self.get_components()? == T::components() {
+			Ok(())
+		} else {
+			Err(Error::InvalidValue)
+		}
+	}
+
+	/// Fails with `Error::InvalidValue` if `T` does not match the image's
+	/// depth and components or the host's row layout does not fit them, and
+	/// with `Error::Unsupported` for OpenGL textures
+	pub fn get_descriptor<T>(&self) -> Result<ImageDescriptor<'_, T>>
+	where
+		T: PixelFormat,
+	{
+		self.check_pixel_format::<T>()?;
 		let bounds = self.get_bounds()?;
 		let row_bytes = self.get_row_bytes()?;
 		let ptr = self.get_data()?;
+		check_layout::<T>(bounds, row_bytes, ptr)?;
 
-		Ok(ImageDescriptor::new(bounds, row_bytes, ptr))
+		Ok(unsafe { ImageDescriptor::new(bounds, row_bytes, ptr) })
 	}
 
-	fn get_descriptor_mut<T>(&mut self) -> Result<ImageDescriptorMut<T>>
+	pub fn get_descriptor_mut<T>(&mut self) -> Result<ImageDescriptorMut<'_, T>>
 	where
 		T: PixelFormat,
 	{
+		self.check_pixel_format::<T>()?;
 		let bounds = self.get_bounds()?;
 		let row_bytes = self.get_row_bytes()?;
-		let mut ptr = self.get_data()?;
+		let ptr = self.get_data()?;
+		check_layout::<T>(bounds, row_bytes, ptr)?;
 
-		Ok(ImageDescriptorMut::new(bounds, row_bytes, ptr))
+		Ok(unsafe { ImageDescriptorMut::new(bounds, row_bytes, ptr) })
 	}
 
-	pub fn get_tiles_mut<T>(&mut self, count: usize) -> Result<Vec<ImageTileMut<T>>>
+	pub fn get_tiles_mut<T>(&mut self, count: usize) -> Result<Vec<ImageTileMut<'_, T>>>
 	where
 		T: PixelFormat,
 	{
-		let bounds = self.get_bounds()?;
-		let row_bytes = self.get_row_bytes()?;
-		let mut ptr = self.get_data()?;
-
-		Ok(ImageDescriptorMut::new(bounds, row_bytes, ptr).into_tiles(count))
+		Ok(self.get_descriptor_mut()?.into_tiles(count))
 	}
 
 	fn drop_image(&mut self) -> Result<()> {
@@ -561,9 +944,17 @@ properties_newtype!(ParamPage);
 properties_newtype!(ParamGroup);
 properties_newtype!(ParamPushButton);
 properties_newtype!(ParamChoice);
+properties_newtype!(ParamStrChoice);
+properties_newtype!(ParamDescriptor);
 
 properties_newtype!(ParameterSet);
 
+impl ParamDescriptor {
+	pub fn into_group(self) -> ParamGroup {
+		ParamGroup(self.0)
+	}
+}
+
 impl DescribeInContextInArgs {}
 
 impl HasProperties<EffectInstance> for ImageEffectHandle {
@@ -630,10 +1021,144 @@ impl ImageEffectHandle {
 		))
 	}
 
+	fn post(&self, message_type: MessageType, text: &str) -> Result<Int> {
+		let text = CString::new(text)?;
+		Ok(suite_call!(message in self.message;
+			self.inner as *mut std::os::raw::c_void, message_type.as_ptr() as *const _, std::ptr::null(), MESSAGE_FORMAT.as_ptr() as *const _, text.as_ptr()))
+	}
+
+	/// Shows `text` to the user, or writes it to the host log for `MessageType::Log`
+	pub fn post_message(&self, message_type: MessageType, text: &str) -> Result<()> {
+		message_result(self.post(message_type, text)?)
+	}
+
+	pub fn error(&self, text: &str) -> Result<()> {
+		self.post_message(MessageType::Error, text)
+	}
+
+	pub fn warning(&self, text: &str) -> Result<()> {
+		self.post_message(MessageType::Warning, text)
+	}
+
+	pub fn message(&self, text: &str) -> Result<()> {
+		self.post_message(MessageType::Message, text)
+	}
+
+	pub fn log(&self, text: &str) -> Result<()> {
+		self.post_message(MessageType::Log, text)
+	}
+
+	/// Asks the user a yes/no question, blocking until it is answered
+	pub fn question(&self, text: &str) -> Result<MessageReply> {
+		MessageReply::from_status(self.post(MessageType::Question, text)?)
+	}
+
+	/// Shows a message on the effect until it is cleared. Hosts without
+	/// persistent messages get a one-off message instead.
+	pub fn set_persistent_message(&self, message_type: MessageType, text: &str) -> Result<()> {
+		match self.message_v2 {
+			Some(ref message_v2) => {
+				let text = CString::new(text)?;
+				suite_fn!(setPersistentMessage in message_v2;
+					self.inner as *mut std::os::raw::c_void, message_type.as_ptr() as *const _, std::ptr::null(), MESSAGE_FORMAT.as_ptr() as *const _, text.as_ptr())
+			}
+			None => self.post_message(message_type, text),
+		}
+	}
+
+	/// Current time of the host's timeline. Hosts without a timeline, or that
+	/// refuse to share it, fail with `Error::Unsupported`.
+	pub fn get_time(&self) -> Result<Time> {
+		let mut time: Time = 0.0;
+		suite_fn!(getTime in self.time_line; self.inner as *mut std::os::raw::c_void, &mut time as *mut Time)?;
+		Ok(time)
+	}
+
+	/// Moves the host's playhead, failing with `Error::Unsupported` where the
+	/// plugin may not drive the timeline. Only call it from interactive
+	/// actions such as InstanceChanged, never while rendering.
+	pub fn goto_time(&self, time: Time) -> Result<()> {
+		suite_fn!(gotoTime in self.time_line; self.inner as *mut std::os::raw::c_void, time)
+	}
+
+	/// First and last frame of the host's timeline, `Error::Unsupported` as for `get_time`
+	pub fn get_time_bounds(&self) -> Result<(Time, Time)> {
+		let (mut first, mut last): (Time, Time) = (0.0, 0.0);
+		suite_fn!(getTimeBounds in self.time_line;
+			self.inner as *mut std::os::raw::c_void, &mut first as *mut Time, &mut last as *mut Time)?;
+		Ok((first, last))
+	}
+
+	/// Allocates scratch pixels for `bounds` from the host's image memory
+	pub fn image_memory<T>(&self, bounds: RectI) -> Result<ImageMemory<T>>
+	where
+		T: PixelFormat,
+	{
+		ImageMemory::new(self.inner, self.image_effect.clone(), bounds)
+	}
+
+	/// Shows a progress bar until the returned guard is dropped or ended.
+	/// `message_id` identifies the message on hosts with the V2 progress suite.
+	pub fn progress(&self, label: &str, message_id: Option<&str>) -> Result<Progress> {
+		let label = CString::new(label)?;
+		let handle = self.inner as *mut std::os::raw::c_void;
+		match self.progress_v2 {
+			Some(ref progress_v2) => {
+				let message_id = message_id.map(CString::new).transpose()?;
+				let message_id_ptr = message_id
+					.as_ref()
+					.map(|message_id| message_id.as_ptr())
+					.unwrap_or(std::ptr::null());
+				suite_fn!(progressStart in progress_v2; handle, label.as_ptr(), message_id_ptr)?;
+			}
+			None => suite_fn!(progressStart in self.progress; handle, label.as_ptr())?,
+		}
+		Ok(Progress {
+			effect: self.clone(),
+			active: true,
+		})
+	}
+
+	/// Clears the persistent message, does nothing on hosts without them.
+	// without the V2 message suite persistent messages fall back to message boxes
+	pub(crate) fn has_persistent_messages(&self) -> bool {
+		self.message_v2.is_some()
+	}
+
+	pub fn clear_persistent_message(&self) -> Result<()> {
+		match self.message_v2 {
+			Some(ref message_v2) => {
+				suite_fn!(clearPersistentMessage in message_v2; self.inner as *mut std::os::raw::c_void)
+			}
+			None => Ok(()),
+		}
+	}
+
+	// identifies the effect instance across actions
+	pub(crate) fn key(&self) -> usize {
+		self.inner as usize
+	}
+
+	/// Text added to every record `HostLogger` writes for this instance,
+	/// such as the name of the clip or file being processed
+	pub fn set_log_context(&self, context: &str) {
+		set_log_context(self.key(), Some(context.to_owned()))
+	}
+
+	pub fn clear_log_context(&self) {
+		set_log_context(self.key(), None)
+	}
+
 	pub fn abort(&self) -> Result<Bool> {
 		Ok(suite_call!(abort in self.image_effect; self.inner) != 0)
 	}
 
+	/// A copy of `abort` that worker threads can poll, see `PluginContext::abortable`
+	pub fn abort_poll(&self) -> Result<AbortPoll> {
+		let abort = self.image_effect.abort.ok_or(Error::SuiteNotInitialized)?;
+		Ok(AbortPoll::new(abort, self.inner))
+	}
+
 	pub fn parameter_set(&self) -> Result<ParamSetHandle> {
 		let parameters_set_handle = {
 			let mut parameters_set_handle = std::ptr::null_mut();
@@ -768,12 +1293,66 @@ impl ParamSetHandle {
 				self.inner, name_buf.as_ptr() as *const _, &mut param_handle as *mut _, &mut param_properties as *mut _)?;
 			(param_handle, param_properties)
 		};
-		Ok(ParamHandle::new(
+		let mut handle = ParamHandle::new(
 			param_handle,
 			param_properties,
 			self.property.clone(),
 			self.parameter.clone(),
-		))
+		);
+		if T::IS_CHOICE {
+			handle.choice = match handle.get_param_type()? {
+				ParamType::StrChoice => ChoiceStorage::Name,
+				// unknown to hosts other than Natron
+				_ if handle.get::<choice::HostCanAddOptions>().unwrap_or(false) => {
+					ChoiceStorage::Label
+				}
+				_ => ChoiceStorage::Index,
+			};
+		}
+		Ok(handle)
+	}
+
+	pub fn edit_begin(&self, name: &str) -> Result<()> {
+		let name_buf = CString::new(name)?.into_bytes_with_nul();
+		suite_fn!(paramEditBegin in self.parameter; self.inner, name_buf.as_ptr() as *const _)
+	}
+
+	pub fn edit_end(&self) -> Result<()> {
+		suite_fn!(paramEditEnd in self.parameter; self.inner)
+	}
+
+	/// Starts an undo block named `name`. Every parameter change made until
+	/// the returned guard is dropped (or ended) becomes a single undo step.
+	pub fn edit(&self, name: &str) -> Result<ParamEdit> {
+		self.edit_begin(name)?;
+		Ok(ParamEdit {
+			param_set: self.clone(),
+			active: true,
+		})
+	}
+
+	/// Properties of an already defined parameter of any type
+	pub fn param_descriptor(&self, name: &str) -> Result<ParamDescriptor> {
+		let name_buf = CString::new(name)?.into_bytes_with_nul();
+		let mut param_handle = std::ptr::null_mut();
+		let mut param_properties = std::ptr::null_mut();
+		suite_fn!(paramGetHandle in self.parameter;
+			self.inner, name_buf.as_ptr() as *const _, &mut param_handle as *mut _, &mut param_properties as *mut _)?;
+		Ok(ParamDescriptor::new(param_properties, self.property.clone()))
+	}
+
+	pub fn define_params<P>(&mut self) -> Result<()>
+	where
+		P: OfxParams,
+	{
+		P::define(self)
+	}
+
+	pub fn fetch_params<P>(&self) -> Result<P::Handles>
+	where
+		P: OfxParams,
+	{
+		P::fetch(self)
 	}
 
 	pub fn param_define_double(&mut self, name: &str) -> Result<ParamDouble> {
@@ -792,6 +1371,20 @@ impl ParamSetHandle {
 		self.param_define(ParamType::String, name)
 	}
 
+	/// Defines a string parameter holding a file name, see `FileMode`
+	pub fn param_define_file_path(&mut self, name: &str, mode: FileMode) -> Result<ParamString> {
+		let mut param = self.param_define_string(name)?;
+		param.set_string_type(ParamStringType::FilePath)?;
+		param.set_file_path_exists(mode == FileMode::Open)?;
+		Ok(param)
+	}
+
+	pub fn param_define_directory_path(&mut self, name: &str) -> Result<ParamString> {
+		let mut param = self.param_define_string(name)?;
+		param.set_string_type(ParamStringType::DirectoryPath)?;
+		Ok(param)
+	}
+
 	pub fn param_define_group(&mut self, name: &str) -> Result<ParamGroup> {
 		self.param_define(ParamType::Group, name)
 	}
@@ -807,6 +1400,30 @@ impl ParamSetHandle {
 	pub fn param_define_choice(&mut self, name: &str) -> Result<ParamChoice> {
 		self.param_define(ParamType::Choice, name)
 	}
+
+	pub fn param_define_str_choice(&mut self, name: &str) -> Result<ParamStrChoice> {
+		self.param_define(ParamType::StrChoice, name)
+	}
+
+	/// Defines a choice parameter with options and default taken from `E`
+	pub fn param_define_choice_enum<E>(&mut self, name: &str) -> Result<ParamChoice>
+	where
+		E: ChoiceEnum,
+	{
+		let mut param = self.param_define_choice(name)?;
+		param.set_choice_enum::<E>()?;
+		Ok(param)
+	}
+
+	/// Defines a string-valued choice parameter with options and default taken from `E`
+	pub fn param_define_str_choice_enum<E>(&mut self, name: &str) -> Result<ParamStrChoice>
+	where
+		E: ChoiceEnum,
+	{
+		let mut param = self.param_define_str_choice(name)?;
+		param.set_choice_enum::<E>()?;
+		Ok(param)
+	}
 }
 
 impl AsProperties for ImageEffectHost {
@@ -860,4 +1477,37 @@ mod tests {
 		handle.get::<property::TypeProp::Property>();
 		handle.get::<property::IsBackground::Property>();
 	}
+
+	#[test]
+	fn progress_cancelled() {
+		assert!(progress_result(eOfxStatus_OK).is_ok());
+		assert!(matches!(progress_result(eOfxStatus_ReplyNo), Err(Error::Aborted)));
+		assert!(matches!(
+			progress_result(eOfxStatus_ErrBadHandle),
+			Err(Error::InvalidHandle)
+		));
+	}
+
+	#[test]
+	fn choice_labels() {
+		let labels: Vec<String> = ["Custom", "Draft", "Best"]
+			.iter()
+			.map(|label| label.to_string())
+			.collect();
+		assert!(label_position(&labels, "Best") == Some(2));
+		assert!(label_position(&labels, "Normal") == None);
+	}
+
+	#[test]
+	fn message_replies() {
+		assert!(MessageReply::from_status(eOfxStatus_ReplyYes).ok() == Some(MessageReply::Yes));
+		assert!(MessageReply::from_status(eOfxStatus_ReplyNo).ok() == Some(MessageReply::No));
+		assert!(MessageReply::from_status(eOfxStatus_OK).is_err());
+		assert!(message_result(eOfxStatus_OK).is_ok());
+		assert!(message_result(eOfxStatus_ReplyDefault).is_ok());
+		assert!(matches!(
+			message_result(eOfxStatus_ErrBadHandle),
+			Err(Error::InvalidHandle)
+		));
+	}
 }
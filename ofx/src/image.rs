@@ -1,11 +1,23 @@
-use enums::{BitDepth, ImageComponent};
+use enums::{BitDepth, ImageComponent, ImageType};
+use result::*;
 use std::marker::PhantomData;
+use tile::TileGrid;
 use types::*;
 
-pub trait ChannelFormat {
+pub trait ChannelFormat: Sized {
 	fn range_max() -> f32;
 	fn from_f32(src: f32) -> Self;
 	fn to_f32(&self) -> f32;
+
+	/// Integer and float channels of the same size are told apart by overriding this
+	#[inline]
+	fn bit_depth() -> BitDepth {
+		match 8 * std::mem::size_of::<Self>() {
+			8 => BitDepth::Byte,
+			16 => BitDepth::Short,
+			_ => BitDepth::Float,
+		}
+	}
 }
 
 impl ChannelFormat for f32 {
@@ -23,6 +35,25 @@ impl ChannelFormat for f32 {
 	}
 }
 
+impl ChannelFormat for Half {
+	#[inline]
+	fn range_max() -> f32 {
+		1.0
+	}
+	#[inline]
+	fn from_f32(src: f32) -> Self {
+		Half::from_f32(src)
+	}
+	#[inline]
+	fn to_f32(&self) -> f32 {
+		Half::to_f32(*self)
+	}
+	#[inline]
+	fn bit_depth() -> BitDepth {
+		BitDepth::Half
+	}
+}
+
 impl ChannelFormat for u16 {
 	#[inline]
 	fn range_max() -> f32 {
@@ -73,6 +104,19 @@ pub trait PixelFormatRGBA: PixelFormatRGB {
 	fn a(&self) -> &Self::ChannelValue;
 	fn a_mut(&mut self) -> &mut Self::ChannelValue;
 }
+pub trait PixelFormatYUVA: PixelFormat {
+	fn new(
+		y: Self::ChannelValue,
+		u: Self::ChannelValue,
+		v: Self::ChannelValue,
+		a: Self::ChannelValue,
+	) -> Self;
+	fn y(&self) -> &Self::ChannelValue;
+	fn u(&self) -> &Self::ChannelValue;
+	fn v(&self) -> &Self::ChannelValue;
+	fn a(&self) -> &Self::ChannelValue;
+}
+
 pub trait PixelFormatAlpha: PixelFormat + ChannelFormat {}
 
 pub trait ScaleMix {
@@ -111,6 +155,67 @@ where
 	}
 }
 
+/// Conversion between premultiplied and straight colour. Unpremultiplying
+/// leaves the colour of fully transparent pixels as it is.
+pub trait Premultiply {
+	fn premultiplied(&self) -> Self;
+	fn unpremultiplied(&self) -> Self;
+}
+
+impl<T> Premultiply for T
+where
+	T: PixelFormatRGBA,
+{
+	// alpha is left untouched, it would not survive a round trip through
+	// f32 at integer depths
+	fn premultiplied(&self) -> Self {
+		let a = self.a().to_f32();
+		let mut pixel = *self;
+		*pixel.r_mut() = T::ChannelValue::from_f32(self.r().to_f32() * a);
+		*pixel.g_mut() = T::ChannelValue::from_f32(self.g().to_f32() * a);
+		*pixel.b_mut() = T::ChannelValue::from_f32(self.b().to_f32() * a);
+		pixel
+	}
+
+	fn unpremultiplied(&self) -> Self {
+		let a = self.a().to_f32();
+		if a <= 0.0 {
+			return *self;
+		}
+		let mut pixel = *self;
+		*pixel.r_mut() = T::ChannelValue::from_f32(self.r().to_f32() / a);
+		*pixel.g_mut() = T::ChannelValue::from_f32(self.g().to_f32() / a);
+		*pixel.b_mut() = T::ChannelValue::from_f32(self.b().to_f32() / a);
+		pixel
+	}
+}
+
+/// Applies a colour operation to straight colour: when `image_type` is
+/// `PreMultiplied` the pixel is unpremultiplied before `op` and the result
+/// premultiplied again, otherwise `op` sees the pixel as it is.
+pub fn with_unpremultiplied<T, F>(image_type: ImageType, pixel: &T, op: F) -> T
+where
+	T: PixelFormatRGBA,
+	F: FnOnce(&T) -> T,
+{
+	if image_type == ImageType::PreMultiplied {
+		op(&pixel.unpremultiplied()).premultiplied()
+	} else {
+		op(pixel)
+	}
+}
+
+/// `with_unpremultiplied` over a row, writing to `dst`
+pub fn row_with_unpremultiplied<T, F>(image_type: ImageType, src: &[T], dst: &mut [T], mut op: F)
+where
+	T: PixelFormatRGBA,
+	F: FnMut(&T) -> T,
+{
+	for (dst, src) in dst.iter_mut().zip(src) {
+		*dst = with_unpremultiplied(image_type, src, &mut op);
+	}
+}
+
 impl ScaleMix for f32 {
 	fn scaled(&self, scale: &RGBAColourD) -> Self {
 		*self * scale.a as f32
@@ -165,6 +270,24 @@ impl ScaleMix for u16 {
 	}
 }
 
+impl ScaleMix for Half {
+	fn scaled(&self, scale: &RGBAColourD) -> Self {
+		Half::from_f32(self.to_f32() * scale.a as f32)
+	}
+
+	fn mix(&self, wet: &Self, mix: f32) -> Self {
+		if mix <= 0.0 {
+			*self
+		} else if mix >= 1.0 {
+			*wet
+		} else {
+			let a0 = mix;
+			let a1 = 1.0 - a0;
+			Half::from_f32(wet.to_f32() * a0 + self.to_f32() * a1)
+		}
+	}
+}
+
 pub trait PixelFormat: Sized + Copy + Clone {
 	type ChannelValue: ChannelFormat;
 
@@ -193,12 +316,7 @@ pub trait PixelFormat: Sized + Copy + Clone {
 
 	#[inline]
 	fn bit_depth() -> BitDepth {
-		match Self::num_bits_depth() {
-			8 => BitDepth::Byte,
-			16 => BitDepth::Short,
-			32 => BitDepth::Float,
-			_ => BitDepth::Float,
-		}
+		Self::ChannelValue::bit_depth()
 	}
 }
 
@@ -279,6 +397,10 @@ macro_rules! pixel_format_yuva {
 	($yuva:ty, $channel_value:ty) => {
 		impl PixelFormat for $yuva {
 			type ChannelValue = $channel_value;
+			#[inline]
+			fn components() -> ImageComponent {
+				ImageComponent::YUVA
+			}
 			fn channel(&self, i: usize) -> &Self::ChannelValue {
 				match i {
 					0 => &self.y,
@@ -298,6 +420,34 @@ macro_rules! pixel_format_yuva {
 				}
 			}
 		}
+
+		impl PixelFormatYUVA for $yuva {
+			fn new(
+				y: Self::ChannelValue,
+				u: Self::ChannelValue,
+				v: Self::ChannelValue,
+				a: Self::ChannelValue,
+			) -> Self {
+				Self { y, u, v, a }
+			}
+
+			#[inline]
+			fn y(&self) -> &Self::ChannelValue {
+				&self.y
+			}
+			#[inline]
+			fn u(&self) -> &Self::ChannelValue {
+				&self.u
+			}
+			#[inline]
+			fn v(&self) -> &Self::ChannelValue {
+				&self.v
+			}
+			#[inline]
+			fn a(&self) -> &Self::ChannelValue {
+				&self.a
+			}
+		}
 	};
 }
 
@@ -370,200 +520,333 @@ macro_rules! pixel_format_alpha {
 
 pixel_format_rgba!(RGBAColourB, u8);
 pixel_format_rgba!(RGBAColourS, u16);
+pixel_format_rgba!(RGBAColourH, Half);
 pixel_format_rgba!(RGBAColourF, f32);
 pixel_format_rgb!(RGBColourB, u8);
 pixel_format_rgb!(RGBColourS, u16);
+pixel_format_rgb!(RGBColourH, Half);
 pixel_format_rgb!(RGBColourF, f32);
 pixel_format_alpha!(u8);
 pixel_format_alpha!(u16);
+pixel_format_alpha!(Half);
 pixel_format_alpha!(f32);
 pixel_format_yuva!(YUVAColourB, u8);
 pixel_format_yuva!(YUVAColourS, u16);
 pixel_format_yuva!(YUVAColourF, f32);
 
-pub struct ImageBuffer<'a, T>
-where
-	T: PixelFormat,
-{
+// Pixels of an image in host memory: `data` addresses pixel (x1, y1) and
+// rows are `row_bytes` apart, running backwards in memory when negative.
+struct RawImage<T> {
 	bounds: RectI,
 	row_bytes: isize,
-	t_size: usize,
-	data: &'a mut u8,
+	data: *mut u8,
 	pixel_type: PhantomData<T>,
 }
 
-impl<'a, T> Clone for ImageBuffer<'a, T>
-where
-	T: PixelFormat,
-{
+impl<T> Clone for RawImage<T> {
 	fn clone(&self) -> Self {
-		// TODO: possibly unsound
-		let data = self.data as *const u8;
-		ImageBuffer {
-			bounds: self.bounds,
-			row_bytes: self.row_bytes,
-			t_size: self.t_size,
-			data: unsafe { &mut *(data as *mut u8) },
-			pixel_type: PhantomData,
-		}
+		*self
 	}
 }
 
-impl<'a, T> ImageBuffer<'a, T>
+impl<T> Copy for RawImage<T> {}
+
+impl<T> RawImage<T>
 where
 	T: PixelFormat,
 {
 	fn new(bounds: RectI, row_bytes: Int, data: *mut u8) -> Self {
-		let t_size = std::mem::size_of::<T>();
-		ImageBuffer {
+		let raw = RawImage {
 			bounds,
 			row_bytes: row_bytes as isize,
-			t_size,
-			data: unsafe { &mut *data },
+			data,
 			pixel_type: PhantomData,
-		}
+		};
+		assert!(raw.is_valid(), "Invalid image of {} bytes per row at {:?}", row_bytes, data);
+		raw
 	}
 
-	#[inline]
-	pub fn byte_offset(&self, x: Int, y: Int) -> isize {
-		(y - self.bounds.y1) as isize * self.row_bytes + (x - self.bounds.x1) as isize
+	// rows do not overlap and pixels are aligned; empty images may be null
+	fn is_valid(&self) -> bool {
+		if self.width() == 0 || self.height() == 0 {
+			return true;
+		}
+		let aligned = self.data as usize & (std::mem::align_of::<T>() - 1) == 0
+			&& self.row_bytes as usize & (std::mem::align_of::<T>() - 1) == 0;
+		!self.data.is_null()
+			&& aligned
+			&& (self.height() == 1
+				|| self.row_bytes.unsigned_abs() >= self.width() * std::mem::size_of::<T>())
 	}
 
-	pub fn bounds(&self) -> RectI {
-		self.bounds
+	fn width(&self) -> usize {
+		(self.bounds.x2 - self.bounds.x1).max(0) as usize
 	}
 
-	pub fn stride_bytes(&self) -> isize {
-		self.row_bytes
+	fn height(&self) -> usize {
+		(self.bounds.y2 - self.bounds.y1).max(0) as usize
 	}
 
-	pub fn bytes(&self) -> usize {
-		self.row_bytes.abs() as usize * (self.bounds.y2 - self.bounds.y1) as usize
+	fn byte_offset(&self, x: Int, y: Int) -> isize {
+		(y - self.bounds.y1) as isize * self.row_bytes
+			+ (x - self.bounds.x1) as isize * std::mem::size_of::<T>() as isize
 	}
 
-	pub fn dimensions(&self) -> (u32, u32) {
-		(
-			(self.bounds.x2 - self.bounds.x1) as u32,
-			(self.bounds.y2 - self.bounds.y1) as u32,
-		)
+	fn row_ptr(&self, y: Int) -> *mut T {
+		assert!(
+			y >= self.bounds.y1 && y < self.bounds.y2,
+			"Row {} outside of {}..{}",
+			y,
+			self.bounds.y1,
+			self.bounds.y2
+		);
+		self.data
+			.wrapping_offset(self.byte_offset(self.bounds.x1, y)) as *mut T
 	}
 
-	fn pixel_bytes(&self) -> usize {
-		self.t_size
+	// Safety: the caller must hold a borrow of the image covering row `y`
+	unsafe fn row<'a>(&self, y: Int) -> &'a [T] {
+		let ptr = self.row_ptr(y);
+		if self.width() == 0 {
+			&[]
+		} else {
+			std::slice::from_raw_parts(ptr, self.width())
+		}
 	}
 
-	pub fn ptr(&self, offset: isize) -> *const u8 {
-		unsafe {
-			let ptr: *const u8 = &*self.data;
-			ptr.offset(offset)
+	// Safety: the caller must hold the only borrow of the image covering row `y`
+	unsafe fn row_mut<'a>(&self, y: Int) -> &'a mut [T] {
+		let ptr = self.row_ptr(y);
+		if self.width() == 0 {
+			&mut []
+		} else {
+			std::slice::from_raw_parts_mut(ptr, self.width())
 		}
 	}
 
-	pub fn ptr_mut(&mut self, offset: isize) -> *mut u8 {
-		unsafe {
-			let ptr: *mut u8 = &mut *self.data;
-			ptr.offset(offset)
+	// the pixels of `rect`, which must lie within the bounds
+	fn sub_rect(self, rect: RectI) -> Self {
+		assert!(
+			rect.x1 >= self.bounds.x1
+				&& rect.y1 >= self.bounds.y1
+				&& rect.x2 <= self.bounds.x2
+				&& rect.y2 <= self.bounds.y2
+				&& rect.x1 <= rect.x2
+				&& rect.y1 <= rect.y2,
+			"Tile {}..{} x {}..{} outside of the image",
+			rect.x1,
+			rect.x2,
+			rect.y1,
+			rect.y2
+		);
+		RawImage {
+			bounds: rect,
+			data: self.data.wrapping_offset(self.byte_offset(rect.x1, rect.y1)),
+			..self
 		}
 	}
 
-	fn make_slice(&self, x: Int, y: Int, length: usize) -> &[T] {
-		let start = self.byte_offset(x, y);
-		unsafe { std::slice::from_raw_parts(self.ptr(start) as *const T, length) }
+	// rows above `y` and rows from `y` on, `y` clamped to the bounds
+	fn split_at_row(self, y: Int) -> (Self, Self) {
+		let y = y.max(self.bounds.y1).min(self.bounds.y2);
+		let top = RawImage {
+			bounds: RectI {
+				y2: y,
+				..self.bounds
+			},
+			..self
+		};
+		let rest = RawImage {
+			bounds: RectI {
+				y1: y,
+				..self.bounds
+			},
+			data: self
+				.data
+				.wrapping_offset(self.byte_offset(self.bounds.x1, y)),
+			..self
+		};
+		(top, rest)
 	}
+}
 
-	fn make_slice_mut(&mut self, x: Int, y: Int, length: usize) -> &mut [T] {
-		let start = self.byte_offset(x, y);
-		unsafe { std::slice::from_raw_parts_mut(self.ptr_mut(start) as *mut T, length) }
+// checks a layout reported by the host before a descriptor is built on it
+pub(crate) fn check_layout<T>(bounds: RectI, row_bytes: Int, data: VoidPtrMut) -> Result<()>
+where
+	T: PixelFormat,
+{
+	let raw = RawImage::<T> {
+		bounds,
+		row_bytes: row_bytes as isize,
+		data: data as *mut u8,
+		pixel_type: PhantomData,
+	};
+	if raw.is_valid() {
+		Ok(())
+	} else {
+		error!("Invalid image of {} bytes per row at {:?}", row_bytes, data);
+		Err(Error::InvalidValue)
 	}
+}
 
-	fn row(&self, y: Int) -> &[T] {
-		self.make_slice(
-			self.bounds.x1,
-			y,
-			(self.bounds.x2 - self.bounds.x1) as usize,
-		)
-	}
+/// Read only view of image pixels, borrowed for `'a`
+pub struct ImageBuffer<'a, T>
+where
+	T: PixelFormat,
+{
+	raw: RawImage<T>,
+	lifetime: PhantomData<&'a [T]>,
+}
+
+/// Mutable view of image pixels, borrowed for `'a`. It can be split into
+/// disjoint views of its rows, which can be sent to other threads.
+pub struct ImageBufferMut<'a, T>
+where
+	T: PixelFormat,
+{
+	raw: RawImage<T>,
+	lifetime: PhantomData<&'a mut [T]>,
+}
 
-	fn row_mut(&mut self, y: Int) -> &mut [T] {
-		let x1 = self.bounds.x1;
-		let x2 = self.bounds.x2;
-		self.make_slice_mut(x1, y, (x2 - x1) as usize)
+// same rules as &[T] and &mut [T]
+unsafe impl<'a, T> Send for ImageBuffer<'a, T> where T: PixelFormat + Sync {}
+unsafe impl<'a, T> Sync for ImageBuffer<'a, T> where T: PixelFormat + Sync {}
+unsafe impl<'a, T> Send for ImageBufferMut<'a, T> where T: PixelFormat + Send {}
+unsafe impl<'a, T> Sync for ImageBufferMut<'a, T> where T: PixelFormat + Sync {}
+
+impl<'a, T> Clone for ImageBuffer<'a, T>
+where
+	T: PixelFormat,
+{
+	fn clone(&self) -> Self {
+		*self
 	}
+}
 
-	fn chunk(&mut self, y1: Int, y2: Int) -> Self {
-		let y1 = y1.max(self.bounds.y1);
-		let y2 = y2.min(self.bounds.y2);
-		let bounds = RectI {
-			x1: self.bounds.x1,
-			y1,
-			x2: self.bounds.x2,
-			y2,
-		};
-		debug!("Chunk {}-{}", y1, y2);
-		// TODO: potentially unsound
-		let offset = self.byte_offset(self.bounds.x1, y1);
-		let ptr_mut = self.ptr_mut(offset);
-		let data = unsafe { &mut *ptr_mut };
-		ImageBuffer {
-			bounds,
-			row_bytes: self.row_bytes,
-			t_size: self.t_size,
-			data,
-			pixel_type: PhantomData,
+impl<'a, T> Copy for ImageBuffer<'a, T> where T: PixelFormat {}
+
+macro_rules! image_buffer_layout {
+	() => {
+		/// Offset in bytes of pixel (x, y) from pixel (x1, y1)
+		#[inline]
+		pub fn byte_offset(&self, x: Int, y: Int) -> isize {
+			self.raw.byte_offset(x, y)
 		}
-	}
 
-	pub fn chunks_mut(mut self, chunk_size: usize) -> impl Iterator<Item = ImageBuffer<'a, T>> {
-		let rows = (self.bounds.y2 - self.bounds.y1) as usize;
-		let n_chunks = (rows + chunk_size - 1) / chunk_size;
-		let rows_per_chunk = chunk_size;
-		let y1 = self.bounds.y1;
-		(0..n_chunks).map(move |chunk| {
-			self.chunk(
-				y1 + (chunk * rows_per_chunk) as Int,
-				y1 + ((chunk + 1) * rows_per_chunk) as Int,
-			)
-		})
-	}
+		pub fn bounds(&self) -> RectI {
+			self.raw.bounds
+		}
+
+		pub fn stride_bytes(&self) -> isize {
+			self.raw.row_bytes
+		}
+
+		pub fn bytes(&self) -> usize {
+			self.raw.row_bytes.unsigned_abs() * self.raw.height()
+		}
+
+		pub fn dimensions(&self) -> (u32, u32) {
+			(self.raw.width() as u32, self.raw.height() as u32)
+		}
+
+		pub fn row(&self, y: Int) -> &[T] {
+			unsafe { self.raw.row(y) }
+		}
+	};
 }
 
-pub struct Row<'a, T>
+impl<'a, T> ImageBuffer<'a, T>
 where
 	T: PixelFormat,
 {
-	y: Int,
-	data: ImageBuffer<'a, T>,
+	image_buffer_layout!();
+
+	pub fn ptr(&self, offset: isize) -> *const u8 {
+		self.raw.data.wrapping_offset(offset)
+	}
+
+	/// Pixels `x1..x2` of row `y`, living as long as the image
+	pub fn row_range(&self, x1: Int, x2: Int, y: Int) -> &'a [T] {
+		let x0 = self.raw.bounds.x1;
+		let row: &'a [T] = unsafe { self.raw.row(y) };
+		&row[(x1 - x0) as usize..(x2 - x0) as usize]
+	}
+
+	pub fn rows(&self) -> impl Iterator<Item = (Int, &'a [T])> {
+		let raw = self.raw;
+		(raw.bounds.y1..raw.bounds.y2).map(move |y| (y, unsafe { raw.row(y) }))
+	}
 }
 
-pub struct RowWalk<'a, T>
+impl<'a, T> ImageBufferMut<'a, T>
 where
 	T: PixelFormat,
 {
-	data: ImageBuffer<'a, T>,
-	y: Int,
-	last_y: Int,
-}
+	image_buffer_layout!();
 
-impl<'a, T> RowWalk<'a, T> where T: PixelFormat {}
+	pub fn ptr_mut(&mut self, offset: isize) -> *mut u8 {
+		self.raw.data.wrapping_offset(offset)
+	}
 
-impl<'a, T> Iterator for RowWalk<'a, T>
-where
-	T: PixelFormat,
-{
-	type Item = Row<'a, T>;
-
-	fn next(&mut self) -> Option<Row<'a, T>> {
-		if self.y < self.last_y {
-			let row = Row {
-				data: self.data.clone(),
-				y: self.y,
-			};
-			self.y += 1;
-			Some(row)
-		} else {
-			None
+	pub fn row_mut(&mut self, y: Int) -> &mut [T] {
+		unsafe { self.raw.row_mut(y) }
+	}
+
+	pub fn row_range_mut(&mut self, x1: Int, x2: Int, y: Int) -> &mut [T] {
+		let x0 = self.raw.bounds.x1;
+		&mut self.row_mut(y)[(x1 - x0) as usize..(x2 - x0) as usize]
+	}
+
+	pub fn as_buffer(&self) -> ImageBuffer<'_, T> {
+		ImageBuffer {
+			raw: self.raw,
+			lifetime: PhantomData,
+		}
+	}
+
+	/// A shorter lived mutable view of the same pixels
+	pub fn reborrow(&mut self) -> ImageBufferMut<'_, T> {
+		ImageBufferMut {
+			raw: self.raw,
+			lifetime: PhantomData,
 		}
 	}
+
+	/// Splits into the rows above `y` and the rows from `y` on
+	pub fn split_at_row(self, y: Int) -> (Self, Self) {
+		let (top, rest) = self.raw.split_at_row(y);
+		(
+			ImageBufferMut {
+				raw: top,
+				lifetime: PhantomData,
+			},
+			ImageBufferMut {
+				raw: rest,
+				lifetime: PhantomData,
+			},
+		)
+	}
+
+	/// Disjoint views of `chunk_size` rows each, the last one possibly shorter
+	pub fn chunks_mut(self, chunk_size: usize) -> impl Iterator<Item = ImageBufferMut<'a, T>> {
+		let chunk_size = chunk_size.max(1) as Int;
+		let mut rest = Some(self);
+		std::iter::from_fn(move || {
+			let buffer = rest.take()?;
+			if buffer.raw.height() == 0 {
+				return None;
+			}
+			let y = buffer.raw.bounds.y1 + chunk_size;
+			let (chunk, tail) = buffer.split_at_row(y);
+			rest = Some(tail);
+			Some(chunk)
+		})
+	}
+
+	pub fn rows_mut(self) -> impl Iterator<Item = (Int, &'a mut [T])> {
+		let raw = self.raw;
+		(raw.bounds.y1..raw.bounds.y2).map(move |y| (y, unsafe { raw.row_mut(y) }))
+	}
 }
 
 #[derive(Clone)]
@@ -578,30 +861,41 @@ pub struct ImageDescriptorMut<'a, T>
 where
 	T: PixelFormat,
 {
-	data: ImageBuffer<'a, T>,
+	data: ImageBufferMut<'a, T>,
 }
 
+/// A band of rows of an image, disjoint from every other tile of it
 pub struct ImageTileMut<'a, T>
 where
 	T: PixelFormat,
 {
 	pub y1: Int,
 	pub y2: Int,
-	data: ImageBuffer<'a, T>,
+	data: ImageBufferMut<'a, T>,
 }
 
 impl<'a, T> ImageDescriptor<'a, T>
 where
 	T: PixelFormat,
 {
-	pub fn new(bounds: RectI, row_bytes: Int, ptr: VoidPtrMut) -> Self {
+	/// # Safety
+	/// `ptr` must address pixel (x1, y1) of an image of `T` with the given
+	/// bounds and row stride, valid for reads for `'a`.
+	pub unsafe fn new(bounds: RectI, row_bytes: Int, ptr: VoidPtrMut) -> Self {
 		ImageDescriptor {
-			data: ImageBuffer::new(bounds, row_bytes, ptr as *mut u8),
+			data: ImageBuffer {
+				raw: RawImage::new(bounds, row_bytes, ptr as *mut u8),
+				lifetime: PhantomData,
+			},
 		}
 	}
 
-	pub fn data(&self) -> ImageBuffer<T> {
-		self.data.clone()
+	pub fn data(&self) -> ImageBuffer<'a, T> {
+		self.data
+	}
+
+	pub fn bounds(&self) -> RectI {
+		self.data.bounds()
 	}
 
 	pub fn row(&self, y: Int) -> &[T] {
@@ -609,8 +903,7 @@ where
 	}
 
 	pub fn row_range(&self, x1: Int, x2: Int, y: Int) -> &[T] {
-		let slice = self.row(y);
-		&slice[(x1 - self.data.bounds.x1) as usize..(x2 - self.data.bounds.x1) as usize]
+		self.data.row_range(x1, x2, y)
 	}
 }
 
@@ -618,9 +911,16 @@ impl<'a, T> ImageDescriptorMut<'a, T>
 where
 	T: PixelFormat,
 {
-	pub fn new(bounds: RectI, row_bytes: Int, ptr: VoidPtrMut) -> Self {
+	/// # Safety
+	/// `ptr` must address pixel (x1, y1) of an image of `T` with the given
+	/// bounds and row stride, valid for reads and writes for `'a` and not
+	/// accessed through any other pointer meanwhile.
+	pub unsafe fn new(bounds: RectI, row_bytes: Int, ptr: VoidPtrMut) -> Self {
 		ImageDescriptorMut {
-			data: ImageBuffer::new(bounds, row_bytes, ptr as *mut u8),
+			data: ImageBufferMut {
+				raw: RawImage::new(bounds, row_bytes, ptr as *mut u8),
+				lifetime: PhantomData,
+			},
 		}
 	}
 
@@ -628,29 +928,54 @@ where
 		self.data.row_mut(y)
 	}
 
-	pub fn data(&mut self) -> ImageBuffer<T> {
-		self.data.clone()
+	pub fn data(&mut self) -> ImageBufferMut<'_, T> {
+		self.data.reborrow()
+	}
+
+	pub fn into_data(self) -> ImageBufferMut<'a, T> {
+		self.data
 	}
 
 	pub fn row_range(&mut self, x1: Int, x2: Int, y: Int) -> &mut [T] {
-		let x0 = self.data.bounds.x1;
-		let slice = self.row(y);
-		let x1 = (x1 - x0) as usize;
-		let x2 = (x2 - x0) as usize;
-		&mut slice[x1..x2]
+		self.data.row_range_mut(x1, x2, y)
+	}
+
+	pub fn bounds(&self) -> RectI {
+		self.data.bounds()
+	}
+
+	// start of row `y`, callers must not create overlapping slices from it
+	pub(crate) fn row_ptr(&self, y: Int) -> *mut T {
+		self.data.raw.row_ptr(y)
 	}
 
+	/// Views of the tiles of `grid`, in the grid's order. Tiles of a grid
+	/// never overlap, so each view can be written independently.
+	pub fn into_tile_grid(self, grid: &TileGrid) -> Vec<ImageBufferMut<'a, T>> {
+		let raw = self.data.raw;
+		grid.tiles()
+			.map(|tile| ImageBufferMut {
+				raw: raw.sub_rect(tile),
+				lifetime: PhantomData,
+			})
+			.collect()
+	}
+
+	// Safety: callers must not hand out overlapping tiles at the same time
+	pub(crate) unsafe fn tile_unchecked(&self, tile: RectI) -> ImageBufferMut<'_, T> {
+		ImageBufferMut {
+			raw: self.data.raw.sub_rect(tile),
+			lifetime: PhantomData,
+		}
+	}
+
+	/// Splits the image into at most `n_chunks` bands of rows
 	pub fn into_tiles(self, n_chunks: usize) -> Vec<ImageTileMut<'a, T>> {
-		let (width, height) = self.data.dimensions();
-		let rows_per_chunk = height as usize / n_chunks;
+		let height = self.data.raw.height();
+		let rows_per_chunk = height.div_ceil(n_chunks.max(1));
 		self.data
-			.chunks_mut(rows_per_chunk as usize)
-			.enumerate()
-			.map(|(chunk_index, chunk)| {
-				let y1 = (chunk_index * rows_per_chunk) as Int;
-				let y2 = (height as Int).min(y1 + rows_per_chunk as Int);
-				ImageTileMut::new(y1, y2, chunk)
-			})
+			.chunks_mut(rows_per_chunk)
+			.map(ImageTileMut::new)
 			.collect()
 	}
 }
@@ -659,19 +984,208 @@ impl<'a, T> ImageTileMut<'a, T>
 where
 	T: PixelFormat,
 {
-	pub fn new(y1: Int, y2: Int, data: ImageBuffer<'a, T>) -> Self {
-		ImageTileMut { y1, y2, data }
+	pub(crate) fn new(data: ImageBufferMut<'a, T>) -> Self {
+		let bounds = data.bounds();
+		ImageTileMut {
+			y1: bounds.y1,
+			y2: bounds.y2,
+			data,
+		}
 	}
 
+	pub fn bounds(&self) -> RectI {
+		self.data.bounds()
+	}
+
+	/// Row `y` in image coordinates, between `y1` and `y2`
 	pub fn row(&mut self, y: Int) -> &mut [T] {
 		self.data.row_mut(y)
 	}
 
 	pub fn row_range(&mut self, x1: Int, x2: Int, y: Int) -> &mut [T] {
-		let x0 = self.data.bounds.x1;
-		let slice = self.row(y);
-		let x1 = (x1 - x0) as usize;
-		let x2 = (x2 - x0) as usize;
-		&mut slice[x1..x2]
+		self.data.row_range_mut(x1, x2, y)
+	}
+
+	pub fn into_data(self) -> ImageBufferMut<'a, T> {
+		self.data
+	}
+}
+
+mod tests {
+	use super::*;
+
+	#[test]
+	fn host_layouts_are_checked() {
+		let bounds = RectI {
+			x1: 0,
+			y1: 0,
+			x2: 4,
+			y2: 2,
+		};
+		let mut pixels = [0f32; 8];
+		let data = pixels.as_mut_ptr() as VoidPtrMut;
+		assert!(check_layout::<f32>(bounds, 16, data).is_ok());
+		assert!(check_layout::<f32>(bounds, -16, data).is_ok());
+		assert!(check_layout::<f32>(bounds, 12, data).is_err());
+		assert!(check_layout::<f32>(bounds, 18, data).is_err());
+		assert!(check_layout::<f32>(bounds, 16, std::ptr::null_mut()).is_err());
+		assert!(check_layout::<f32>(bounds, 16, (data as usize + 1) as VoidPtrMut).is_err());
+		let empty = RectI { x2: 0, ..bounds };
+		assert!(check_layout::<f32>(empty, 0, std::ptr::null_mut()).is_ok());
+	}
+
+	#[test]
+	fn depth_of_16_bit_channels() {
+		assert_eq!(RGBAColourS::bit_depth(), BitDepth::Short);
+		assert_eq!(RGBAColourH::bit_depth(), BitDepth::Half);
+		assert_eq!(RGBColourH::bit_depth(), BitDepth::Half);
+		assert_eq!(<Half as PixelFormat>::bit_depth(), BitDepth::Half);
+		assert_eq!(RGBAColourH::components(), ImageComponent::RGBA);
+		let pixel = RGBAColourH::new(Half(0), Half(0), Half(0), Half::from_f32(0.5));
+		assert_eq!(pixel.a().to_f32(), 0.5);
+	}
+
+	#[test]
+	fn premultiplication() {
+		let straight = RGBAColourF {
+			r: 1.0,
+			g: 0.5,
+			b: 0.0,
+			a: 0.5,
+		};
+		let premultiplied = straight.premultiplied();
+		assert_eq!((premultiplied.r, premultiplied.g, premultiplied.a), (0.5, 0.25, 0.5));
+		assert_eq!(premultiplied.unpremultiplied().g, 0.5);
+		let transparent = RGBAColourF::new(0.25, 0.0, 0.0, 0.0);
+		assert_eq!(transparent.unpremultiplied().r, 0.25);
+
+		// an operation on straight colour, adding 0.25 to red
+		let brighten = |pixel: &RGBAColourF| RGBAColourF::new(pixel.r + 0.25, pixel.g, pixel.b, pixel.a);
+		let result = with_unpremultiplied(ImageType::PreMultiplied, &premultiplied, brighten);
+		assert_eq!((result.r, result.g), (0.625, 0.25));
+		let result = with_unpremultiplied(ImageType::UnPreMultiplied, &straight, brighten);
+		assert_eq!((result.r, result.g), (1.25, 0.5));
+	}
+
+	const BOUNDS: RectI = RectI {
+		x1: 10,
+		y1: 20,
+		x2: 13,
+		y2: 25,
+	};
+
+	// rows padded to 4 pixels, stored bottom up or top down
+	fn pixels(rows: usize) -> Vec<RGBAColourB> {
+		vec![RGBAColourB::new(0, 0, 0, 0); rows * 4]
+	}
+
+	fn descriptor(data: &mut [RGBAColourB], flipped: bool) -> ImageDescriptorMut<'_, RGBAColourB> {
+		let row_bytes = 4 * std::mem::size_of::<RGBAColourB>() as Int;
+		if flipped {
+			let last_row = &mut data[data.len() - 4] as *mut RGBAColourB;
+			unsafe { ImageDescriptorMut::new(BOUNDS, -row_bytes, last_row as VoidPtrMut) }
+		} else {
+			unsafe { ImageDescriptorMut::new(BOUNDS, row_bytes, data.as_mut_ptr() as VoidPtrMut) }
+		}
+	}
+
+	#[test]
+	fn byte_offset_counts_bytes() {
+		let mut data = pixels(5);
+		let mut image = descriptor(&mut data, false);
+		assert_eq!(image.data().byte_offset(10, 20), 0);
+		assert_eq!(image.data().byte_offset(12, 21), 16 + 2 * 4);
+		image.row_range(12, 13, 21)[0] = RGBAColourB::new(1, 2, 3, 4);
+		assert_eq!(data[4 + 2].b, 3);
+	}
+
+	#[test]
+	fn negative_row_bytes() {
+		let mut data = pixels(5);
+		let mut image = descriptor(&mut data, true);
+		assert_eq!(image.data().byte_offset(10, 21), -16);
+		for y in 20..25 {
+			image.row(y)[0].r = y as u8;
+		}
+		let rows: Vec<u8> = data.chunks(4).map(|row| row[0].r).collect();
+		assert_eq!(rows, vec![24, 23, 22, 21, 20]);
+	}
+
+	#[test]
+	fn tiles_are_disjoint_and_in_image_coordinates() {
+		for flipped in &[false, true] {
+			let mut data = pixels(5);
+			let tiles = descriptor(&mut data, *flipped).into_tiles(2);
+			let ranges: Vec<(Int, Int)> = tiles.iter().map(|tile| (tile.y1, tile.y2)).collect();
+			assert_eq!(ranges, vec![(20, 23), (23, 25)]);
+			std::thread::scope(|scope| {
+				for mut tile in tiles {
+					scope.spawn(move || {
+						for y in tile.y1..tile.y2 {
+							for pixel in tile.row(y) {
+								pixel.g += y as u8;
+							}
+						}
+					});
+				}
+			});
+			let mut image = descriptor(&mut data, *flipped);
+			for y in 20..25 {
+				assert!(image.row(y).iter().all(|pixel| pixel.g == y as u8));
+			}
+		}
+	}
+
+	#[test]
+	fn tile_grid_views() {
+		let mut data = pixels(5);
+		let window = RectI {
+			x1: 11,
+			y1: 21,
+			x2: 20,
+			y2: 30,
+		};
+		let grid = TileGrid::new(window, BOUNDS).tile_size(1, 2);
+		let tiles = descriptor(&mut data, true).into_tile_grid(&grid);
+		assert_eq!(tiles.len(), 4);
+		for (index, mut tile) in tiles.into_iter().enumerate() {
+			let bounds = tile.bounds();
+			for y in bounds.y1..bounds.y2 {
+				for pixel in tile.row_mut(y) {
+					pixel.a = index as u8 + 1;
+				}
+			}
+		}
+		let mut image = descriptor(&mut data, true);
+		assert_eq!(image.row(20).iter().map(|p| p.a).collect::<Vec<_>>(), vec![0, 0, 0]);
+		assert_eq!(image.row(21).iter().map(|p| p.a).collect::<Vec<_>>(), vec![0, 1, 2]);
+		assert_eq!(image.row(22).iter().map(|p| p.a).collect::<Vec<_>>(), vec![0, 1, 2]);
+		assert_eq!(image.row(23).iter().map(|p| p.a).collect::<Vec<_>>(), vec![0, 3, 4]);
+		assert_eq!(image.row(24).iter().map(|p| p.a).collect::<Vec<_>>(), vec![0, 3, 4]);
+	}
+
+	#[test]
+	fn more_tiles_than_rows() {
+		let mut data = pixels(5);
+		let tiles = descriptor(&mut data, false).into_tiles(8);
+		assert_eq!(tiles.len(), 5);
+		assert_eq!((tiles[4].y1, tiles[4].y2), (24, 25));
+	}
+
+	#[test]
+	fn split_rows() {
+		let mut data = pixels(5);
+		let (top, rest) = descriptor(&mut data, false).into_data().split_at_row(22);
+		assert_eq!((top.bounds().y1, top.bounds().y2), (20, 22));
+		assert_eq!((rest.bounds().y1, rest.bounds().y2), (22, 25));
+		let rows: Vec<Int> = rest.rows_mut().map(|(y, _)| y).collect();
+		assert_eq!(rows, vec![22, 23, 24]);
+	}
+
+	#[test]
+	#[should_panic]
+	fn rows_outside_bounds_panic() {
+		let mut data = pixels(5);
+		descriptor(&mut data, false).row(25);
 	}
 }
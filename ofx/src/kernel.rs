@@ -0,0 +1,490 @@
+use enums::*;
+use handle::*;
+use image::*;
+use ofx_sys::OfxMultiThreadSuiteV1;
+use property::*;
+use result::*;
+use std::convert::TryFrom;
+use thread::*;
+use tile::*;
+use types::*;
+
+/// The pixel formats kernels are dispatched on, RGBA and alpha at every depth
+pub trait KernelFormat: PixelFormat + ScaleMix + Send + Sync {
+	/// Alpha of the same depth, as read from masks
+	type Mask: PixelFormatAlpha + Send + Sync;
+
+	/// Alpha formats keep only `a`
+	fn from_rgba(colour: &RGBAColourF) -> Self;
+	/// Alpha formats read as black
+	fn to_rgba(&self) -> RGBAColourF;
+}
+
+macro_rules! kernel_format {
+	(rgba $format:ty, $mask:ty) => {
+		impl KernelFormat for $format {
+			type Mask = $mask;
+
+			fn from_rgba(colour: &RGBAColourF) -> Self {
+				PixelFormatRGBA::new(
+					ChannelFormat::from_f32(colour.r),
+					ChannelFormat::from_f32(colour.g),
+					ChannelFormat::from_f32(colour.b),
+					ChannelFormat::from_f32(colour.a),
+				)
+			}
+
+			fn to_rgba(&self) -> RGBAColourF {
+				RGBAColourF {
+					r: self.r().to_f32(),
+					g: self.g().to_f32(),
+					b: self.b().to_f32(),
+					a: self.a().to_f32(),
+				}
+			}
+		}
+	};
+	(alpha $format:ty) => {
+		impl KernelFormat for $format {
+			type Mask = $format;
+
+			fn from_rgba(colour: &RGBAColourF) -> Self {
+				ChannelFormat::from_f32(colour.a)
+			}
+
+			fn to_rgba(&self) -> RGBAColourF {
+				RGBAColourF {
+					r: 0.0,
+					g: 0.0,
+					b: 0.0,
+					a: ChannelFormat::to_f32(self),
+				}
+			}
+		}
+	};
+}
+
+kernel_format!(rgba RGBAColourF, f32);
+kernel_format!(rgba RGBAColourB, u8);
+kernel_format!(rgba RGBAColourS, u16);
+kernel_format!(rgba RGBAColourH, Half);
+kernel_format!(alpha f32);
+kernel_format!(alpha u8);
+kernel_format!(alpha u16);
+kernel_format!(alpha Half);
+
+/// Code generic over the pixel format, called back by `dispatch_format`
+/// with the format picked at run time
+pub trait FormatDispatch {
+	type Output;
+	fn dispatch<T: KernelFormat>(self) -> Result<Self::Output>;
+}
+
+/// Calls `target` with the pixel format of `depth` and `components`; other
+/// formats than RGBA and alpha fail with `Error::Unsupported`
+pub fn dispatch_format<D>(depth: BitDepth, components: ImageComponent, target: D) -> Result<D::Output>
+where
+	D: FormatDispatch,
+{
+	match (depth, components) {
+		(BitDepth::Float, ImageComponent::RGBA) => target.dispatch::<RGBAColourF>(),
+		(BitDepth::Byte, ImageComponent::RGBA) => target.dispatch::<RGBAColourB>(),
+		(BitDepth::Short, ImageComponent::RGBA) => target.dispatch::<RGBAColourS>(),
+		(BitDepth::Half, ImageComponent::RGBA) => target.dispatch::<RGBAColourH>(),
+		(BitDepth::Float, ImageComponent::Alpha) => target.dispatch::<f32>(),
+		(BitDepth::Byte, ImageComponent::Alpha) => target.dispatch::<u8>(),
+		(BitDepth::Short, ImageComponent::Alpha) => target.dispatch::<u16>(),
+		(BitDepth::Half, ImageComponent::Alpha) => target.dispatch::<Half>(),
+		_ => Err(Error::Unsupported),
+	}
+}
+
+/// Computes each output pixel from the source pixel at the same position
+pub trait MapKernel: Sync {
+	fn map<T: KernelFormat>(&self, src: &T) -> T;
+}
+
+/// Computes each output pixel from the source and mask pixels at the same
+/// position; the mask reads as 0 outside its bounds
+pub trait ZipKernel: Sync {
+	fn zip<T: KernelFormat>(&self, src: &T, mask: &T::Mask) -> T;
+}
+
+/// Computes each output pixel from its position, in pixel coordinates
+pub trait GenerateKernel: Sync {
+	fn generate<T: KernelFormat>(&self, x: Int, y: Int) -> T;
+}
+
+fn map_row<T, K>(kernel: &K, src: &[T], dst: &mut [T])
+where
+	T: KernelFormat,
+	K: MapKernel,
+{
+	for (dst, src) in dst.iter_mut().zip(src) {
+		*dst = kernel.map(src);
+	}
+}
+
+// `x1` is the position of the first pixel of `src` and `dst`; the mask is
+// converted to the depth of `T` pixel by pixel
+fn zip_row<T, M, K>(kernel: &K, src: &[T], mask: &[M], mask_x1: Int, x1: Int, dst: &mut [T])
+where
+	T: KernelFormat,
+	M: PixelFormatAlpha,
+	K: ZipKernel,
+{
+	for (x, (dst, src)) in (x1..).zip(dst.iter_mut().zip(src)) {
+		let mask = usize::try_from(x - mask_x1)
+			.ok()
+			.and_then(|index| mask.get(index))
+			.map_or(0.0, ChannelFormat::to_f32);
+		*dst = kernel.zip(src, &<T::Mask as ChannelFormat>::from_f32(mask));
+	}
+}
+
+// the part of row `y` of `tile` that `src` covers, empty outside it
+fn covered(src: RectI, tile: RectI, y: Int) -> (Int, Int) {
+	if y < src.y1 || y >= src.y2 {
+		return (tile.x1, tile.x1);
+	}
+	let x1 = src.x1.max(tile.x1).min(tile.x2);
+	(x1, src.x2.min(tile.x2).max(x1))
+}
+
+// writes transparent black around `start..end` of `row` and returns that part
+fn clear_outside<T>(row: &mut [T], start: usize, end: usize) -> &mut [T]
+where
+	T: KernelFormat,
+{
+	let black = T::from_rgba(&RGBAColourF {
+		r: 0.0,
+		g: 0.0,
+		b: 0.0,
+		a: 0.0,
+	});
+	let (head, rest) = row.split_at_mut(start);
+	let (covered, tail) = rest.split_at_mut(end - start);
+	for pixel in head.iter_mut().chain(tail) {
+		*pixel = black;
+	}
+	covered
+}
+
+fn generate_row<T, K>(kernel: &K, x1: Int, y: Int, dst: &mut [T])
+where
+	T: KernelFormat,
+	K: GenerateKernel,
+{
+	for (x, dst) in (x1..).zip(dst.iter_mut()) {
+		*dst = kernel.generate(x, y);
+	}
+}
+
+struct MapImage<'a, K> {
+	multi_thread: &'a OfxMultiThreadSuiteV1,
+	abort: Option<AbortPoll>,
+	kernel: &'a K,
+	src: &'a Image,
+	dst: &'a mut Image,
+	render_window: RectI,
+}
+
+impl<'a, K> FormatDispatch for MapImage<'a, K>
+where
+	K: MapKernel,
+{
+	type Output = ();
+
+	fn dispatch<T: KernelFormat>(self) -> Result<()> {
+		let kernel = self.kernel;
+		let src = self.src.get_descriptor::<T>()?;
+		let mut dst = self.dst.get_descriptor_mut::<T>()?;
+		let src_bounds = src.bounds();
+		let grid = TileGrid::new(self.render_window, dst.bounds());
+		parallel_tiles(self.multi_thread, self.abort, &mut dst, &grid, &|tile, mut dst| {
+			for y in tile.y1..tile.y2 {
+				let (x1, x2) = covered(src_bounds, tile, y);
+				let row = dst.row_mut(y);
+				let row = clear_outside(row, (x1 - tile.x1) as usize, (x2 - tile.x1) as usize);
+				if x1 < x2 {
+					map_row(kernel, src.row_range(x1, x2, y), row);
+				}
+			}
+			Ok(())
+		})
+	}
+}
+
+struct ZipImage<'a, K> {
+	multi_thread: &'a OfxMultiThreadSuiteV1,
+	abort: Option<AbortPoll>,
+	kernel: &'a K,
+	src: &'a Image,
+	mask: &'a Image,
+	dst: &'a mut Image,
+	render_window: RectI,
+}
+
+impl<'a, K> FormatDispatch for ZipImage<'a, K>
+where
+	K: ZipKernel,
+{
+	type Output = ();
+
+	fn dispatch<T: KernelFormat>(self) -> Result<()> {
+		match self.mask.get_pixel_depth()? {
+			BitDepth::Byte => self.zip::<T, u8>(),
+			BitDepth::Short => self.zip::<T, u16>(),
+			BitDepth::Half => self.zip::<T, Half>(),
+			BitDepth::Float => self.zip::<T, f32>(),
+			BitDepth::None => Err(Error::Unsupported),
+		}
+	}
+}
+
+impl<'a, K> ZipImage<'a, K>
+where
+	K: ZipKernel,
+{
+	// `M` is the mask format, which may differ in depth from `T`
+	fn zip<T, M>(self) -> Result<()>
+	where
+		T: KernelFormat,
+		M: PixelFormatAlpha + Send + Sync,
+	{
+		let kernel = self.kernel;
+		let src = self.src.get_descriptor::<T>()?;
+		let mask = self.mask.get_descriptor::<M>()?;
+		let mut dst = self.dst.get_descriptor_mut::<T>()?;
+		let (src_bounds, mask_bounds) = (src.bounds(), mask.bounds());
+		let grid = TileGrid::new(self.render_window, dst.bounds());
+		parallel_tiles(self.multi_thread, self.abort, &mut dst, &grid, &|tile, mut dst| {
+			for y in tile.y1..tile.y2 {
+				let (x1, x2) = covered(src_bounds, tile, y);
+				let row = dst.row_mut(y);
+				let row = clear_outside(row, (x1 - tile.x1) as usize, (x2 - tile.x1) as usize);
+				if x1 >= x2 {
+					continue;
+				}
+				let mask_row = if y >= mask_bounds.y1 && y < mask_bounds.y2 {
+					mask.row(y)
+				} else {
+					&[]
+				};
+				zip_row(kernel, src.row_range(x1, x2, y), mask_row, mask_bounds.x1, x1, row);
+			}
+			Ok(())
+		})
+	}
+}
+
+struct GenerateImage<'a, K> {
+	multi_thread: &'a OfxMultiThreadSuiteV1,
+	abort: Option<AbortPoll>,
+	kernel: &'a K,
+	dst: &'a mut Image,
+	render_window: RectI,
+}
+
+impl<'a, K> FormatDispatch for GenerateImage<'a, K>
+where
+	K: GenerateKernel,
+{
+	type Output = ();
+
+	fn dispatch<T: KernelFormat>(self) -> Result<()> {
+		let kernel = self.kernel;
+		let mut dst = self.dst.get_descriptor_mut::<T>()?;
+		let grid = TileGrid::new(self.render_window, dst.bounds());
+		parallel_tiles(self.multi_thread, self.abort, &mut dst, &grid, &|tile, mut dst| {
+			for y in tile.y1..tile.y2 {
+				generate_row(kernel, tile.x1, y, dst.row_mut(y));
+			}
+			Ok(())
+		})
+	}
+}
+
+pub(crate) fn map_image<K>(
+	multi_thread: &OfxMultiThreadSuiteV1,
+	abort: Option<AbortPoll>,
+	kernel: &K,
+	src: &Image,
+	dst: &mut Image,
+	render_window: RectI,
+) -> Result<()>
+where
+	K: MapKernel,
+{
+	let (depth, components) = (dst.get_pixel_depth()?, dst.get_components()?);
+	dispatch_format(
+		depth,
+		components,
+		MapImage {
+			multi_thread,
+			abort,
+			kernel,
+			src,
+			dst,
+			render_window,
+		},
+	)
+}
+
+pub(crate) fn zip_image<K>(
+	multi_thread: &OfxMultiThreadSuiteV1,
+	abort: Option<AbortPoll>,
+	kernel: &K,
+	src: &Image,
+	mask: &Image,
+	dst: &mut Image,
+	render_window: RectI,
+) -> Result<()>
+where
+	K: ZipKernel,
+{
+	let (depth, components) = (dst.get_pixel_depth()?, dst.get_components()?);
+	dispatch_format(
+		depth,
+		components,
+		ZipImage {
+			multi_thread,
+			abort,
+			kernel,
+			src,
+			mask,
+			dst,
+			render_window,
+		},
+	)
+}
+
+pub(crate) fn generate_image<K>(
+	multi_thread: &OfxMultiThreadSuiteV1,
+	abort: Option<AbortPoll>,
+	kernel: &K,
+	dst: &mut Image,
+	render_window: RectI,
+) -> Result<()>
+where
+	K: GenerateKernel,
+{
+	let (depth, components) = (dst.get_pixel_depth()?, dst.get_components()?);
+	dispatch_format(
+		depth,
+		components,
+		GenerateImage {
+			multi_thread,
+			abort,
+			kernel,
+			dst,
+			render_window,
+		},
+	)
+}
+
+mod tests {
+	use super::*;
+
+	struct Format;
+
+	impl FormatDispatch for Format {
+		type Output = (BitDepth, ImageComponent);
+
+		fn dispatch<T: KernelFormat>(self) -> Result<Self::Output> {
+			Ok((T::bit_depth(), T::components()))
+		}
+	}
+
+	#[test]
+	fn dispatches_matching_format() {
+		for depth in &[BitDepth::Byte, BitDepth::Short, BitDepth::Half, BitDepth::Float] {
+			for components in &[ImageComponent::RGBA, ImageComponent::Alpha] {
+				let (d, c) = dispatch_format(*depth, *components, Format).unwrap();
+				assert_eq!((d, c), (*depth, *components));
+			}
+		}
+		assert!(matches!(
+			dispatch_format(BitDepth::Float, ImageComponent::RGB, Format),
+			Err(Error::Unsupported)
+		));
+	}
+
+	struct Invert;
+
+	impl MapKernel for Invert {
+		fn map<T: KernelFormat>(&self, src: &T) -> T {
+			let mut dst = *src;
+			for i in 0..T::num_components() {
+				*dst.channel_mut(i) = ChannelFormat::from_f32(1.0 - src.channel(i).to_f32());
+			}
+			dst
+		}
+	}
+
+	impl ZipKernel for Invert {
+		fn zip<T: KernelFormat>(&self, src: &T, mask: &T::Mask) -> T {
+			src.mix(&self.map(src), mask.to_f32())
+		}
+	}
+
+	impl GenerateKernel for Invert {
+		fn generate<T: KernelFormat>(&self, x: Int, _y: Int) -> T {
+			let grey = x as f32 / 4.0;
+			self.map(&T::from_rgba(&RGBAColourF {
+				r: grey,
+				g: grey,
+				b: grey,
+				a: 1.0,
+			}))
+		}
+	}
+
+	#[test]
+	fn rows() {
+		let src = [0u8, 255, 51];
+		let mut dst = [0u8; 3];
+		map_row(&Invert, &src, &mut dst);
+		assert_eq!(dst, [255, 0, 204]);
+
+		// the mask covers x 1..3 of a row starting at x 0
+		zip_row(&Invert, &src, &[255u8, 0], 1, 0, &mut dst);
+		assert_eq!(dst, [0, 0, 51]);
+		// masks of another depth are converted
+		zip_row(&Invert, &src, &[1.0f32, 0.0], 1, 0, &mut dst);
+		assert_eq!(dst, [0, 0, 51]);
+
+		let mut dst = [RGBAColourF::new(0.0, 0.0, 0.0, 0.0); 2];
+		generate_row(&Invert, 1, 0, &mut dst);
+		assert_eq!(dst[1].to_rgba().g, 0.5);
+		assert_eq!(dst[1].to_rgba().a, 0.0);
+		assert_eq!(0.75f32.to_rgba().a, 0.75);
+	}
+
+	#[test]
+	fn outside_the_source() {
+		let src = RectI {
+			x1: 2,
+			y1: 0,
+			x2: 4,
+			y2: 1,
+		};
+		let tile = RectI {
+			x1: 0,
+			y1: 0,
+			x2: 6,
+			y2: 2,
+		};
+		assert_eq!(covered(src, tile, 0), (2, 4));
+		assert_eq!(covered(src, tile, 1), (0, 0));
+		let past = RectI { x1: 8, x2: 9, ..src };
+		assert_eq!(covered(past, tile, 0), (6, 6));
+
+		let mut row = [9u8; 6];
+		assert_eq!(clear_outside(&mut row, 2, 4), [9, 9]);
+		assert_eq!(row, [0, 0, 9, 9, 0, 0]);
+		let mut row = [RGBAColourF::new(1.0, 1.0, 1.0, 1.0); 2];
+		assert!(clear_outside(&mut row, 0, 0).is_empty());
+		assert_eq!(row[1].to_rgba().a, 0.0);
+	}
+}
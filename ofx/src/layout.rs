@@ -0,0 +1,434 @@
+use enums::*;
+use handle::*;
+use property::*;
+use result::*;
+use std::collections::HashSet;
+use types::*;
+
+// values of kOfxParamPageSkipRow and kOfxParamPageSkipColumn
+const SKIP_ROW: &str = "OfxParamPageSkipRow";
+const SKIP_COLUMN: &str = "OfxParamPageSkipColumn";
+
+/// An entry on a page or inside a group
+#[derive(Debug, Clone)]
+pub enum LayoutItem {
+	Param(String),
+	Group(GroupLayout),
+	/// Leaves an empty row, only meaningful directly on a page
+	SkipRow,
+	/// Continues in the next column, only meaningful directly on a page
+	SkipColumn,
+}
+
+#[derive(Debug, Clone)]
+pub struct GroupLayout {
+	name: String,
+	label: Option<String>,
+	hint: Option<String>,
+	open: Option<Bool>,
+	tab: Bool,
+	children: Vec<LayoutItem>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PageLayout {
+	name: String,
+	label: Option<String>,
+	items: Vec<LayoutItem>,
+}
+
+/// Describes how already defined parameters are arranged on pages and in groups.
+///
+/// ```ignore
+/// Layout::new()
+///     .page(
+///         PageLayout::new("Main")
+///             .param("scale")
+///             .skip_row()
+///             .group(GroupLayout::new("components").label("Components").param("scaleR")),
+///     )
+///     .define(&mut param_set, &host)?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Layout {
+	pages: Vec<PageLayout>,
+}
+
+impl GroupLayout {
+	pub fn new(name: &str) -> Self {
+		GroupLayout {
+			name: name.to_owned(),
+			label: None,
+			hint: None,
+			open: None,
+			tab: false,
+			children: Vec::new(),
+		}
+	}
+
+	pub fn label(mut self, label: &str) -> Self {
+		self.label = Some(label.to_owned());
+		self
+	}
+
+	pub fn hint(mut self, hint: &str) -> Self {
+		self.hint = Some(hint.to_owned());
+		self
+	}
+
+	pub fn open(mut self, open: Bool) -> Self {
+		self.open = Some(open);
+		self
+	}
+
+	/// Asks the host to show the group as a tab, ignored by hosts without tabs
+	pub fn tab(mut self) -> Self {
+		self.tab = true;
+		self
+	}
+
+	pub fn param(mut self, name: &str) -> Self {
+		self.children.push(LayoutItem::Param(name.to_owned()));
+		self
+	}
+
+	pub fn group(mut self, group: GroupLayout) -> Self {
+		self.children.push(LayoutItem::Group(group));
+		self
+	}
+}
+
+impl PageLayout {
+	pub fn new(name: &str) -> Self {
+		PageLayout {
+			name: name.to_owned(),
+			label: None,
+			items: Vec::new(),
+		}
+	}
+
+	pub fn label(mut self, label: &str) -> Self {
+		self.label = Some(label.to_owned());
+		self
+	}
+
+	pub fn param(mut self, name: &str) -> Self {
+		self.items.push(LayoutItem::Param(name.to_owned()));
+		self
+	}
+
+	pub fn group(mut self, group: GroupLayout) -> Self {
+		self.items.push(LayoutItem::Group(group));
+		self
+	}
+
+	/// Leaves an empty row, there is no separator line in OFX pages
+	pub fn skip_row(mut self) -> Self {
+		self.items.push(LayoutItem::SkipRow);
+		self
+	}
+
+	pub fn skip_column(mut self) -> Self {
+		self.items.push(LayoutItem::SkipColumn);
+		self
+	}
+
+	/// Page children in host order: groups come before their own children.
+	fn children(&self) -> Vec<LayoutItem> {
+		fn flatten(items: &[LayoutItem], out: &mut Vec<LayoutItem>) {
+			for item in items {
+				match item {
+					LayoutItem::Group(group) => {
+						out.push(LayoutItem::Param(group.name.clone()));
+						flatten(&group.children, out);
+					}
+					other => out.push(other.clone()),
+				}
+			}
+		}
+		let mut out = Vec::new();
+		flatten(&self.items, &mut out);
+		out
+	}
+}
+
+impl Layout {
+	pub fn new() -> Self {
+		Layout::default()
+	}
+
+	pub fn page(mut self, page: PageLayout) -> Self {
+		self.pages.push(page);
+		self
+	}
+
+	/// Defines the groups and pages, parents the listed parameters and checks
+	/// the result against the host's `MaxPages` and `PageRowColumnCount`.
+	/// Hosts that allow a single page get every page merged into it, hosts
+	/// without pages only get the groups. Hosts that do not report
+	/// `MaxPages` are taken to allow any number.
+	pub fn define(&self, param_set: &mut ParamSetHandle, host: &ImageEffectHost) -> Result<()> {
+		self.validate_names()?;
+		for page in &self.pages {
+			for item in &page.items {
+				match item {
+					LayoutItem::Param(name) => {
+						check_defined(param_set, name)?;
+					}
+					LayoutItem::Group(group) => define_group(param_set, group, None)?,
+					LayoutItem::SkipRow | LayoutItem::SkipColumn => {}
+				}
+			}
+		}
+
+		// unreported means unlimited, as in `HostCapabilities`
+		let pages = match self.pages_for(host.get_max_pages().unwrap_or(-1))? {
+			Some(pages) => pages,
+			None => {
+				debug!("Host has no pages, only groups of the layout are defined");
+				return Ok(());
+			}
+		};
+
+		let limits = host.get_page_row_column_count().ok();
+		for page in pages {
+			let children = page.children();
+			if let Some(limits) = limits {
+				if !fits_on_page(&children, limits.x, limits.y) {
+					error!(
+						"Page {:?} does not fit in the host's {} rows by {} columns",
+						page.name, limits.x, limits.y
+					);
+					return Err(Error::InvalidLayout);
+				}
+			}
+			let mut param = param_set.param_define_page(&page.name)?;
+			if let Some(label) = &page.label {
+				param.set_label(label)?;
+			}
+			let names: Vec<&str> = children
+				.iter()
+				.map(|item| match item {
+					LayoutItem::Param(name) => name.as_str(),
+					LayoutItem::SkipRow => SKIP_ROW,
+					LayoutItem::SkipColumn => SKIP_COLUMN,
+					LayoutItem::Group(_) => unreachable!(),
+				})
+				.collect();
+			param.set_children(&names)?;
+		}
+		Ok(())
+	}
+
+	// the pages to define for a host allowing `max_pages`, -1 for unlimited;
+	// None when the host has no pages
+	fn pages_for(&self, max_pages: Int) -> Result<Option<Vec<PageLayout>>> {
+		match max_pages {
+			0 => Ok(None),
+			1 if self.pages.len() > 1 => Ok(Some(vec![self.merged_page()])),
+			_ if max_pages > 0 && self.pages.len() > max_pages as usize => {
+				error!(
+					"Layout has {} pages but the host allows at most {}",
+					self.pages.len(),
+					max_pages
+				);
+				Err(Error::InvalidLayout)
+			}
+			_ => Ok(Some(self.pages.clone())),
+		}
+	}
+
+	fn merged_page(&self) -> PageLayout {
+		let mut merged = PageLayout::new(&self.pages[0].name);
+		merged.label = self.pages[0].label.clone();
+		for (index, page) in self.pages.iter().enumerate() {
+			if index > 0 {
+				merged.items.push(LayoutItem::SkipRow);
+			}
+			merged.items.extend(page.items.iter().cloned());
+		}
+		merged
+	}
+
+	fn validate_names(&self) -> Result<()> {
+		fn visit<'a>(items: &'a [LayoutItem], seen: &mut HashSet<&'a str>) -> Result<()> {
+			for item in items {
+				let name = match item {
+					LayoutItem::Param(name) => name,
+					LayoutItem::Group(group) => {
+						visit(&group.children, seen)?;
+						&group.name
+					}
+					LayoutItem::SkipRow | LayoutItem::SkipColumn => continue,
+				};
+				if !seen.insert(name) {
+					error!("Parameter {:?} appears more than once in the layout", name);
+					return Err(Error::InvalidLayout);
+				}
+			}
+			Ok(())
+		}
+		let mut seen = HashSet::new();
+		for page in &self.pages {
+			if !seen.insert(&page.name) {
+				error!("Page {:?} appears more than once in the layout", page.name);
+				return Err(Error::InvalidLayout);
+			}
+		}
+		let mut seen = HashSet::new();
+		for page in &self.pages {
+			visit(&page.items, &mut seen)?;
+		}
+		Ok(())
+	}
+}
+
+fn check_defined(param_set: &ParamSetHandle, name: &str) -> Result<ParamDescriptor> {
+	param_set.param_descriptor(name).map_err(|_| {
+		error!("Layout refers to undefined parameter {:?}", name);
+		Error::InvalidLayout
+	})
+}
+
+fn define_group(
+	param_set: &mut ParamSetHandle,
+	group: &GroupLayout,
+	parent: Option<&str>,
+) -> Result<()> {
+	let mut param = match param_set.param_descriptor(&group.name) {
+		Ok(existing) => match existing.get_param_type()? {
+			ParamType::Group => existing.into_group(),
+			_ => {
+				error!(
+					"Layout group {:?} is already defined with another parameter type",
+					group.name
+				);
+				return Err(Error::InvalidLayout);
+			}
+		},
+		Err(_) => param_set.param_define_group(&group.name)?,
+	};
+	if let Some(label) = &group.label {
+		param.set_label(label)?;
+	}
+	if let Some(hint) = &group.hint {
+		param.set_hint(hint)?;
+	}
+	if let Some(open) = group.open {
+		param.set_group_open(open)?;
+	}
+	if group.tab {
+		// only some hosts know about tabs
+		param.set_group_is_tab(true).ok();
+	}
+	if let Some(parent) = parent {
+		param.set_parent(parent)?;
+	}
+	for child in &group.children {
+		match child {
+			LayoutItem::Param(name) => check_defined(param_set, name)?.set_parent(&group.name)?,
+			LayoutItem::Group(child) => define_group(param_set, child, Some(&group.name))?,
+			LayoutItem::SkipRow | LayoutItem::SkipColumn => {}
+		}
+	}
+	Ok(())
+}
+
+/// Whether the page children fill at most `rows` by `columns` cells, a
+/// non-positive limit means the host does not constrain that direction.
+fn fits_on_page(children: &[LayoutItem], rows: Int, columns: Int) -> bool {
+	if rows <= 0 || columns <= 0 {
+		return true;
+	}
+	let (mut row, mut column) = (0, 0);
+	for item in children {
+		if column >= columns {
+			return false;
+		}
+		match item {
+			LayoutItem::SkipColumn => {
+				row = 0;
+				column += 1;
+			}
+			_ => {
+				row += 1;
+				if row == rows {
+					row = 0;
+					column += 1;
+				}
+			}
+		}
+	}
+	true
+}
+
+mod tests {
+	use super::*;
+
+	fn params(count: usize) -> Vec<LayoutItem> {
+		(0..count)
+			.map(|i| LayoutItem::Param(format!("p{}", i)))
+			.collect()
+	}
+
+	#[test]
+	fn page_limits() {
+		assert!(fits_on_page(&params(6), 3, 2));
+		assert!(!fits_on_page(&params(7), 3, 2));
+		assert!(fits_on_page(&params(100), 0, 0));
+
+		let mut items = params(1);
+		items.push(LayoutItem::SkipColumn);
+		items.extend(params(3));
+		assert!(fits_on_page(&items, 3, 2));
+		items.push(LayoutItem::SkipRow);
+		assert!(!fits_on_page(&items, 3, 2));
+	}
+
+	#[test]
+	fn groups_precede_children_on_page() {
+		let page = PageLayout::new("Main").param("a").skip_row().group(
+			GroupLayout::new("g")
+				.param("b")
+				.group(GroupLayout::new("h").param("c")),
+		);
+		let names: Vec<String> = page
+			.children()
+			.into_iter()
+			.map(|item| match item {
+				LayoutItem::Param(name) => name,
+				_ => "-".to_owned(),
+			})
+			.collect();
+		assert_eq!(names, ["a", "-", "g", "b", "h", "c"]);
+	}
+
+	#[test]
+	fn duplicates_are_rejected() {
+		let layout = Layout::new()
+			.page(PageLayout::new("One").param("a"))
+			.page(PageLayout::new("Two").group(GroupLayout::new("g").param("a")));
+		assert!(layout.validate_names().is_err());
+
+		let layout = Layout::new()
+			.page(PageLayout::new("One").param("a"))
+			.page(PageLayout::new("Two").param("b"));
+		assert!(layout.validate_names().is_ok());
+		assert_eq!(layout.merged_page().children().len(), 3);
+	}
+
+	#[test]
+	fn page_counts() {
+		let layout = Layout::new()
+			.page(PageLayout::new("One").param("a"))
+			.page(PageLayout::new("Two").param("b"))
+			.page(PageLayout::new("Three").param("c"));
+		let count = |max_pages| layout.pages_for(max_pages).map(|pages| pages.map(|p| p.len()));
+		// hosts that do not report a limit read as -1
+		assert_eq!(count(-1).unwrap(), Some(3));
+		assert_eq!(count(3).unwrap(), Some(3));
+		assert_eq!(count(1).unwrap(), Some(1));
+		assert_eq!(count(0).unwrap(), None);
+		assert!(count(2).is_err());
+	}
+}
@@ -4,6 +4,7 @@
 extern crate ofx_sys;
 #[macro_use]
 extern crate log;
+extern crate ofx_derive;
 
 use std::collections::HashMap;
 use std::ffi::CString;
@@ -19,25 +20,54 @@ mod suites;
 #[macro_use]
 mod util;
 mod action;
+mod capabilities;
+mod changes;
+mod colour;
+mod convolution;
+mod coords;
 mod enums;
 mod handle;
 mod plugin;
 mod property;
+mod thread;
+mod tile;
 mod types;
 #[macro_use]
 mod registry;
 mod image;
+mod kernel;
+mod layout;
+mod logger;
+mod memory;
+mod path;
+mod sampler;
+mod yuv;
 pub use action::*;
+pub use capabilities::*;
+pub use changes::*;
+pub use colour::*;
+pub use convolution::*;
+pub use coords::*;
 pub use enums::*;
 pub use handle::*;
 pub use plugin::*;
 pub use property::*;
 pub use result::*;
+pub use thread::*;
+pub use tile::*;
 pub use types::*;
 pub use util::*;
 pub use image::*;
+pub use kernel::*;
+pub use layout::*;
+pub use logger::*;
+pub use memory::*;
+pub use path::*;
+pub use sampler::*;
+pub use yuv::*;
 use registry::*;
 
+pub use ofx_derive::OfxParams;
 pub use ofx_sys::{OfxHost, OfxPlugin, OfxPropertySetHandle};
 pub use registry::{
 	get_registry, init_registry, main_entry_for_plugin, set_host_for_plugin, Registry,
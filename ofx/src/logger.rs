@@ -0,0 +1,327 @@
+use enums::*;
+use handle::MESSAGE_FORMAT;
+use log::{LevelFilter, Log, Metadata, Record};
+use ofx_sys::*;
+use result::*;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use types::*;
+
+// the host's message suite, shared by all plugins in the bundle
+static HOST_MESSAGE: Mutex<Option<OfxMessageSuiteV1>> = Mutex::new(None);
+// user supplied context per effect instance, see `ImageEffectHandle::set_log_context`
+static INSTANCE_CONTEXT: Mutex<BTreeMap<usize, String>> = Mutex::new(BTreeMap::new());
+
+thread_local! {
+	static SCOPE: RefCell<Option<LogScope>> = const { RefCell::new(None) };
+}
+
+pub(crate) fn attach_log_host(message: OfxMessageSuiteV1) {
+	*HOST_MESSAGE.lock().unwrap_or_else(|e| e.into_inner()) = Some(message);
+}
+
+pub(crate) fn set_log_context(key: usize, context: Option<String>) {
+	let mut contexts = INSTANCE_CONTEXT.lock().unwrap_or_else(|e| e.into_inner());
+	match context {
+		Some(context) => contexts.insert(key, context),
+		None => contexts.remove(&key),
+	};
+}
+
+fn log_context(key: usize) -> Option<String> {
+	INSTANCE_CONTEXT
+		.lock()
+		.unwrap_or_else(|e| e.into_inner())
+		.get(&key)
+		.cloned()
+}
+
+/// The plugin and instance an action is running for, so that records logged
+/// from it can be attributed.
+#[derive(Debug, Clone)]
+pub(crate) struct LogScope {
+	plugin_id: Arc<str>,
+	instance: usize,
+}
+
+pub(crate) struct LogScopeGuard {
+	previous: Option<LogScope>,
+}
+
+impl LogScope {
+	pub(crate) fn new(plugin_id: Arc<str>, instance: VoidPtr) -> Self {
+		LogScope {
+			plugin_id,
+			instance: instance as usize,
+		}
+	}
+
+	pub(crate) fn current() -> Option<LogScope> {
+		SCOPE.with(|scope| scope.borrow().clone())
+	}
+
+	/// Makes this the scope of the current thread until the guard is dropped
+	pub(crate) fn enter(self) -> LogScopeGuard {
+		let previous = SCOPE.with(|scope| scope.borrow_mut().replace(self));
+		LogScopeGuard { previous }
+	}
+}
+
+impl Drop for LogScopeGuard {
+	fn drop(&mut self) {
+		let previous = self.previous.take();
+		SCOPE.with(|scope| *scope.borrow_mut() = previous);
+	}
+}
+
+// Fixed one second window; returns how many records were dropped since the
+// last one let through, or None when this one must be dropped too.
+struct RateLimit {
+	max_per_second: u32,
+	window: Option<Instant>,
+	count: u32,
+	suppressed: u32,
+}
+
+impl RateLimit {
+	fn new(max_per_second: u32) -> Self {
+		RateLimit {
+			max_per_second,
+			window: None,
+			count: 0,
+			suppressed: 0,
+		}
+	}
+
+	fn expired(&self, now: Instant) -> bool {
+		match self.window {
+			Some(start) => now.duration_since(start) >= Duration::from_secs(1),
+			None => true,
+		}
+	}
+
+	fn admit(&mut self, now: Instant) -> Option<u32> {
+		if self.expired(now) {
+			self.window = Some(now);
+			self.count = 0;
+		}
+		if self.count < self.max_per_second {
+			self.count += 1;
+			Some(std::mem::replace(&mut self.suppressed, 0))
+		} else {
+			self.suppressed += 1;
+			None
+		}
+	}
+
+	// nothing left to report once the window is over
+	fn is_idle(&self, now: Instant) -> bool {
+		self.suppressed == 0 && self.expired(now)
+	}
+}
+
+// limits kept before idle ones are dropped
+const RATE_LIMITS_KEPT: usize = 64;
+
+fn format_record(
+	scope: Option<&LogScope>,
+	context: Option<&str>,
+	level: log::Level,
+	args: &fmt::Arguments,
+	suppressed: u32,
+) -> String {
+	let mut text = String::new();
+	if let Some(scope) = scope {
+		text.push_str(&scope.plugin_id);
+		if scope.instance != 0 {
+			text.push_str(&format!("[{:x}]", scope.instance));
+		}
+		if let Some(context) = context {
+			text.push_str(&format!(" ({})", context));
+		}
+		text.push(' ');
+	}
+	text.push_str(&format!("{}: {}", level, args));
+	if suppressed > 0 {
+		text.push_str(&format!(" ({} earlier messages suppressed)", suppressed));
+	}
+	text
+}
+
+/// A `log` backend that writes records to the host's own log through the
+/// message suite, prefixed with the plugin id and effect instance they were
+/// logged from. Records are dropped above `max_per_second` per instance, so a
+/// noisy render loop cannot flood the host or silence the other instances.
+/// Install it once from the plugin's module, instead of another logger:
+///
+/// ```ignore
+/// HostLogger::new(LevelFilter::Warn).install()?;
+/// ```
+///
+/// Records logged before the first plugin is loaded are discarded.
+pub struct HostLogger {
+	level: LevelFilter,
+	max_per_second: u32,
+	// keyed by effect instance, 0 for records logged outside an action
+	limits: Mutex<BTreeMap<usize, RateLimit>>,
+}
+
+impl HostLogger {
+	pub fn new(level: LevelFilter) -> Self {
+		HostLogger {
+			level,
+			max_per_second: 50,
+			limits: Mutex::new(BTreeMap::new()),
+		}
+	}
+
+	pub fn max_per_second(self, max_per_second: u32) -> Self {
+		HostLogger {
+			max_per_second,
+			..self
+		}
+	}
+
+	fn admit(&self, instance: usize, now: Instant) -> Option<u32> {
+		let mut limits = self.limits.lock().unwrap_or_else(|e| e.into_inner());
+		if limits.len() >= RATE_LIMITS_KEPT && !limits.contains_key(&instance) {
+			limits.retain(|_, limit| !limit.is_idle(now));
+		}
+		let max_per_second = self.max_per_second;
+		limits
+			.entry(instance)
+			.or_insert_with(|| RateLimit::new(max_per_second))
+			.admit(now)
+	}
+
+	/// Sets this as the global logger; fails if one is already set
+	pub fn install(self) -> Result<()> {
+		let level = self.level;
+		log::set_logger(Box::leak(Box::new(self))).map_err(|_| Error::LoggerAlreadySet)?;
+		log::set_max_level(level);
+		Ok(())
+	}
+}
+
+impl Log for HostLogger {
+	fn enabled(&self, metadata: &Metadata) -> bool {
+		metadata.level() <= self.level
+	}
+
+	// must not log itself, or it would recurse
+	fn log(&self, record: &Record) {
+		if !self.enabled(record.metadata()) {
+			return;
+		}
+		let message = match *HOST_MESSAGE.lock().unwrap_or_else(|e| e.into_inner()) {
+			Some(message) => message,
+			None => return,
+		};
+		let scope = LogScope::current();
+		let instance = scope.as_ref().map_or(0, |scope| scope.instance);
+		let suppressed = match self.admit(instance, Instant::now()) {
+			Some(suppressed) => suppressed,
+			None => return,
+		};
+		let context = scope.as_ref().and_then(|scope| log_context(scope.instance));
+		let text = format_record(
+			scope.as_ref(),
+			context.as_deref(),
+			record.level(),
+			record.args(),
+			suppressed,
+		);
+		if let (Ok(text), Some(post)) = (CString::new(text), message.message) {
+			unsafe {
+				post(
+					instance as VoidPtrMut,
+					MessageType::Log.as_ptr() as *const _,
+					std::ptr::null(),
+					MESSAGE_FORMAT.as_ptr() as *const _,
+					text.as_ptr(),
+				)
+			};
+		}
+	}
+
+	fn flush(&self) {}
+}
+
+mod tests {
+	use super::*;
+
+	#[test]
+	fn rate_limit_counts_suppressed() {
+		let start = Instant::now();
+		let mut limit = RateLimit::new(2);
+		assert_eq!(limit.admit(start), Some(0));
+		assert_eq!(limit.admit(start), Some(0));
+		assert_eq!(limit.admit(start), None);
+		assert_eq!(limit.admit(start + Duration::from_millis(500)), None);
+		assert_eq!(limit.admit(start + Duration::from_secs(1)), Some(2));
+		assert_eq!(limit.admit(start + Duration::from_secs(1)), Some(0));
+	}
+
+	#[test]
+	fn instances_are_limited_separately() {
+		let start = Instant::now();
+		let logger = HostLogger::new(LevelFilter::Info).max_per_second(1);
+		assert_eq!(logger.admit(1, start), Some(0));
+		assert_eq!(logger.admit(1, start), None);
+		assert_eq!(logger.admit(2, start), Some(0));
+		assert_eq!(logger.admit(1, start + Duration::from_secs(1)), Some(1));
+	}
+
+	#[test]
+	fn idle_limits_are_dropped() {
+		let start = Instant::now();
+		let logger = HostLogger::new(LevelFilter::Info).max_per_second(1);
+		for instance in 0..RATE_LIMITS_KEPT {
+			logger.admit(instance, start);
+		}
+		logger.admit(0, start);
+		let later = start + Duration::from_secs(1);
+		logger.admit(RATE_LIMITS_KEPT, later);
+		let limits = logger.limits.lock().unwrap();
+		// instance 0 still owes a suppressed count
+		assert_eq!(limits.keys().cloned().collect::<Vec<_>>(), vec![0, RATE_LIMITS_KEPT]);
+	}
+
+	#[test]
+	fn prefix() {
+		let scope = LogScope {
+			plugin_id: "net.itadinanta.ofx-rs.basic".into(),
+			instance: 0xbeef,
+		};
+		assert_eq!(
+			format_record(
+				Some(&scope),
+				Some("Source"),
+				log::Level::Warn,
+				&format_args!("slow"),
+				3
+			),
+			"net.itadinanta.ofx-rs.basic[beef] (Source) WARN: slow (3 earlier messages suppressed)"
+		);
+		assert_eq!(
+			format_record(None, None, log::Level::Info, &format_args!("loaded"), 0),
+			"INFO: loaded"
+		);
+	}
+
+	#[test]
+	fn scopes_nest() {
+		let outer = LogScope::new("outer".into(), std::ptr::null()).enter();
+		{
+			let _inner = LogScope::new("inner".into(), std::ptr::null()).enter();
+			assert_eq!(&*LogScope::current().unwrap().plugin_id, "inner");
+		}
+		assert_eq!(&*LogScope::current().unwrap().plugin_id, "outer");
+		drop(outer);
+		assert!(LogScope::current().is_none());
+	}
+}
@@ -0,0 +1,392 @@
+use image::*;
+use ofx_sys::*;
+use result::*;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::rc::Rc;
+use types::*;
+
+fn host_alloc<T>(memory: &OfxMemorySuiteV1, count: usize) -> Result<NonNull<T>> {
+	let bytes = std::mem::size_of::<T>()
+		.checked_mul(count)
+		.ok_or(Error::InvalidValue)?;
+	if bytes == 0 {
+		return Ok(NonNull::dangling());
+	}
+	let mut data: VoidPtrMut = std::ptr::null_mut();
+	suite_fn!(memoryAlloc in memory; std::ptr::null_mut(), bytes, &mut data as *mut VoidPtrMut)?;
+	let data = NonNull::new(data as *mut T).ok_or(Error::InvalidValue)?;
+	// hosts only promise alignment for the usual scalar types
+	if data.as_ptr() as usize & (std::mem::align_of::<T>() - 1) != 0 {
+		error!(
+			"Host memory at {:?} is not aligned to {} bytes",
+			data,
+			std::mem::align_of::<T>()
+		);
+		host_free(memory, data, count);
+		return Err(Error::InvalidValue);
+	}
+	Ok(data)
+}
+
+fn host_free<T>(memory: &OfxMemorySuiteV1, data: NonNull<T>, count: usize) {
+	if std::mem::size_of::<T>() * count != 0 {
+		let status = unsafe { memory.memoryFree.map(|free| free(data.as_ptr() as VoidPtrMut)) };
+		if status != Some(eOfxStatus_OK) {
+			error!("Unable to free host memory at {:?}", data);
+		}
+	}
+}
+
+/// A single value allocated by the host through the memory suite, so that it
+/// counts against the host's memory budget.
+pub struct HostBox<T> {
+	memory: OfxMemorySuiteV1,
+	data: NonNull<T>,
+}
+
+unsafe impl<T: Send> Send for HostBox<T> {}
+unsafe impl<T: Sync> Sync for HostBox<T> {}
+
+impl<T> HostBox<T> {
+	pub(crate) fn new(memory: OfxMemorySuiteV1, value: T) -> Result<Self> {
+		let data = host_alloc::<T>(&memory, 1)?;
+		unsafe { std::ptr::write(data.as_ptr(), value) };
+		Ok(HostBox { memory, data })
+	}
+}
+
+impl<T> Deref for HostBox<T> {
+	type Target = T;
+	fn deref(&self) -> &T {
+		unsafe { self.data.as_ref() }
+	}
+}
+
+impl<T> DerefMut for HostBox<T> {
+	fn deref_mut(&mut self) -> &mut T {
+		unsafe { self.data.as_mut() }
+	}
+}
+
+impl<T> Drop for HostBox<T> {
+	fn drop(&mut self) {
+		unsafe { std::ptr::drop_in_place(self.data.as_ptr()) };
+		host_free(&self.memory, self.data, 1);
+	}
+}
+
+/// A growable array allocated by the host through the memory suite.
+pub struct HostVec<T> {
+	memory: OfxMemorySuiteV1,
+	data: NonNull<T>,
+	len: usize,
+	capacity: usize,
+}
+
+unsafe impl<T: Send> Send for HostVec<T> {}
+unsafe impl<T: Sync> Sync for HostVec<T> {}
+
+impl<T> HostVec<T> {
+	pub(crate) fn with_capacity(memory: OfxMemorySuiteV1, capacity: usize) -> Result<Self> {
+		let data = host_alloc::<T>(&memory, capacity)?;
+		Ok(HostVec {
+			memory,
+			data,
+			len: 0,
+			capacity,
+		})
+	}
+
+	pub fn len(&self) -> usize {
+		self.len
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len == 0
+	}
+
+	pub fn capacity(&self) -> usize {
+		self.capacity
+	}
+
+	/// Makes room for `additional` more elements, moving the contents to a
+	/// new host allocation when needed.
+	pub fn reserve(&mut self, additional: usize) -> Result<()> {
+		let required = self.len.checked_add(additional).ok_or(Error::InvalidValue)?;
+		if required <= self.capacity {
+			return Ok(());
+		}
+		let capacity = grown_capacity(self.capacity, required);
+		let data = host_alloc::<T>(&self.memory, capacity)?;
+		unsafe { std::ptr::copy_nonoverlapping(self.data.as_ptr(), data.as_ptr(), self.len) };
+		host_free(&self.memory, self.data, self.capacity);
+		self.data = data;
+		self.capacity = capacity;
+		Ok(())
+	}
+
+	pub fn push(&mut self, value: T) -> Result<()> {
+		self.reserve(1)?;
+		unsafe { std::ptr::write(self.data.as_ptr().add(self.len), value) };
+		self.len += 1;
+		Ok(())
+	}
+
+	pub fn pop(&mut self) -> Option<T> {
+		if self.len == 0 {
+			None
+		} else {
+			self.len -= 1;
+			Some(unsafe { std::ptr::read(self.data.as_ptr().add(self.len)) })
+		}
+	}
+
+	/// Grows or shrinks to `len` elements, filling new slots with `value`
+	pub fn resize(&mut self, len: usize, value: T) -> Result<()>
+	where
+		T: Clone,
+	{
+		if len > self.len {
+			self.reserve(len - self.len)?;
+			while self.len < len {
+				self.push(value.clone())?;
+			}
+		} else {
+			self.truncate(len);
+		}
+		Ok(())
+	}
+
+	pub fn truncate(&mut self, len: usize) {
+		while self.len > len {
+			self.pop();
+		}
+	}
+
+	pub fn clear(&mut self) {
+		self.truncate(0)
+	}
+}
+
+// at least doubles, so that pushes stay amortised constant time
+fn grown_capacity(capacity: usize, required: usize) -> usize {
+	capacity
+		.checked_mul(2)
+		.map_or(required, |doubled| doubled.max(required))
+		.max(4)
+}
+
+impl<T> Deref for HostVec<T> {
+	type Target = [T];
+	fn deref(&self) -> &[T] {
+		unsafe { std::slice::from_raw_parts(self.data.as_ptr(), self.len) }
+	}
+}
+
+impl<T> DerefMut for HostVec<T> {
+	fn deref_mut(&mut self) -> &mut [T] {
+		unsafe { std::slice::from_raw_parts_mut(self.data.as_ptr(), self.len) }
+	}
+}
+
+impl<T> Drop for HostVec<T> {
+	fn drop(&mut self) {
+		self.clear();
+		host_free(&self.memory, self.data, self.capacity);
+	}
+}
+
+/// Scratch image memory from the host's image cache, created with
+/// `ImageEffectHandle::image_memory`. The host may move it while unlocked,
+/// so pixels are only reachable through a lock.
+pub struct ImageMemory<T>
+where
+	T: PixelFormat,
+{
+	inner: OfxImageMemoryHandle,
+	image_effect: Rc<OfxImageEffectSuiteV1>,
+	bounds: RectI,
+	pixel_type: PhantomData<T>,
+}
+
+/// Keeps an `ImageMemory` locked in place, unlocking it when dropped
+pub struct ImageMemoryLock<'a, T>
+where
+	T: PixelFormat,
+{
+	memory: &'a mut ImageMemory<T>,
+	data: VoidPtrMut,
+}
+
+impl<T> ImageMemory<T>
+where
+	T: PixelFormat,
+{
+	pub(crate) fn new(
+		effect: OfxImageEffectHandle,
+		image_effect: Rc<OfxImageEffectSuiteV1>,
+		bounds: RectI,
+	) -> Result<Self> {
+		let width = (bounds.x2 - bounds.x1).max(0) as usize;
+		let height = (bounds.y2 - bounds.y1).max(0) as usize;
+		let bytes = width
+			.checked_mul(height)
+			.and_then(|pixels| pixels.checked_mul(std::mem::size_of::<T>()))
+			.ok_or(Error::InvalidValue)?;
+		let mut inner = std::ptr::null_mut();
+		suite_fn!(imageMemoryAlloc in image_effect; effect, bytes, &mut inner as *mut OfxImageMemoryHandle)?;
+		Ok(ImageMemory {
+			inner,
+			image_effect,
+			bounds,
+			pixel_type: PhantomData,
+		})
+	}
+
+	pub fn bounds(&self) -> RectI {
+		self.bounds
+	}
+
+	pub fn lock(&mut self) -> Result<ImageMemoryLock<'_, T>> {
+		let mut data: VoidPtrMut = std::ptr::null_mut();
+		suite_fn!(imageMemoryLock in self.image_effect; self.inner, &mut data as *mut VoidPtrMut)?;
+		Ok(ImageMemoryLock { memory: self, data })
+	}
+}
+
+impl<T> Drop for ImageMemory<T>
+where
+	T: PixelFormat,
+{
+	fn drop(&mut self) {
+		let status = unsafe { self.image_effect.imageMemoryFree.map(|free| free(self.inner)) };
+		if status != Some(eOfxStatus_OK) {
+			error!("Unable to free image memory {:?}", self.inner);
+		}
+	}
+}
+
+impl<'a, T> ImageMemoryLock<'a, T>
+where
+	T: PixelFormat,
+{
+	/// The locked pixels, rows packed without padding
+	pub fn image(&mut self) -> ImageDescriptorMut<'_, T> {
+		let bounds = self.memory.bounds;
+		let row_bytes = (bounds.x2 - bounds.x1).max(0) * std::mem::size_of::<T>() as Int;
+		unsafe { ImageDescriptorMut::new(bounds, row_bytes, self.data) }
+	}
+}
+
+impl<'a, T> Drop for ImageMemoryLock<'a, T>
+where
+	T: PixelFormat,
+{
+	fn drop(&mut self) {
+		let status = unsafe {
+			self.memory
+				.image_effect
+				.imageMemoryUnlock
+				.map(|unlock| unlock(self.memory.inner))
+		};
+		if status != Some(eOfxStatus_OK) {
+			error!("Unable to unlock image memory {:?}", self.memory.inner);
+		}
+	}
+}
+
+mod tests {
+	use super::*;
+	use std::alloc::{alloc, dealloc, Layout};
+	use std::os::raw::c_void;
+
+	const HEADER: usize = 16;
+
+	// stands in for the host allocator, keeping the size before each block
+	unsafe extern "C" fn test_alloc(
+		_: *mut c_void,
+		bytes: usize,
+		data: *mut *mut c_void,
+	) -> OfxStatus {
+		let block = alloc(Layout::from_size_align(bytes + HEADER, HEADER).unwrap());
+		*(block as *mut usize) = bytes;
+		*data = block.add(HEADER) as *mut c_void;
+		eOfxStatus_OK
+	}
+
+	unsafe extern "C" fn test_free(data: *mut c_void) -> OfxStatus {
+		let block = (data as *mut u8).sub(HEADER);
+		let bytes = *(block as *const usize);
+		dealloc(block, Layout::from_size_align(bytes + HEADER, HEADER).unwrap());
+		eOfxStatus_OK
+	}
+
+	// hands out address 8, whatever the size
+	unsafe extern "C" fn misaligned_alloc(
+		_: *mut c_void,
+		_: usize,
+		data: *mut *mut c_void,
+	) -> OfxStatus {
+		*data = NonNull::<u64>::dangling().as_ptr() as *mut c_void;
+		eOfxStatus_OK
+	}
+
+	unsafe extern "C" fn ignore_free(_: *mut c_void) -> OfxStatus {
+		eOfxStatus_OK
+	}
+
+	fn memory() -> OfxMemorySuiteV1 {
+		OfxMemorySuiteV1 {
+			memoryAlloc: Some(test_alloc),
+			memoryFree: Some(test_free),
+		}
+	}
+
+	#[test]
+	fn capacity_growth() {
+		assert_eq!(grown_capacity(0, 1), 4);
+		assert_eq!(grown_capacity(4, 5), 8);
+		assert_eq!(grown_capacity(8, 100), 100);
+		assert_eq!(grown_capacity(usize::MAX / 2 + 1, usize::MAX), usize::MAX);
+
+		let mut values = HostVec::with_capacity(memory(), 0).unwrap();
+		for value in 0..9u32 {
+			values.push(value).unwrap();
+		}
+		assert_eq!(values.capacity(), 16);
+		assert_eq!(&values[..], &[0, 1, 2, 3, 4, 5, 6, 7, 8]);
+		values.resize(3, 0).unwrap();
+		assert_eq!(values.pop(), Some(2));
+		assert!(values.reserve(usize::MAX).is_err());
+	}
+
+	#[test]
+	fn zero_sizes_skip_the_host() {
+		let none = OfxMemorySuiteV1 {
+			memoryAlloc: None,
+			memoryFree: None,
+		};
+		let mut units = HostVec::with_capacity(none, 0).unwrap();
+		for _ in 0..100 {
+			units.push(()).unwrap();
+		}
+		assert_eq!(units.len(), 100);
+		assert!(HostBox::new(none, ()).is_ok());
+		assert!(HostVec::<u32>::with_capacity(none, 0).is_ok());
+		assert!(HostVec::<u32>::with_capacity(none, 1).is_err());
+	}
+
+	#[test]
+	fn misaligned_memory_is_rejected() {
+		#[repr(align(16))]
+		struct Aligned(u8);
+
+		let misaligned = OfxMemorySuiteV1 {
+			memoryAlloc: Some(misaligned_alloc),
+			memoryFree: Some(ignore_free),
+		};
+		assert!(HostBox::new(misaligned, Aligned(1)).is_err());
+	}
+}
@@ -0,0 +1,200 @@
+use enums::*;
+use handle::*;
+use result::*;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+// the violation each (effect instance, policy) pair currently shows
+static SHOWING: Mutex<BTreeMap<(usize, usize), String>> = Mutex::new(BTreeMap::new());
+static NEXT_POLICY: AtomicUsize = AtomicUsize::new(0);
+
+fn showing() -> std::sync::MutexGuard<'static, BTreeMap<(usize, usize), String>> {
+	SHOWING.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// Whether `text` must be posted for `key`: a new violation always is, a
+// changed one only where persistent messages can be replaced, since the
+// fallback pops up a dialog every time
+fn should_post(key: (usize, usize), text: &str, replaceable: bool) -> bool {
+	match showing().get(&key) {
+		None => true,
+		Some(shown) => replaceable && shown != text,
+	}
+}
+
+/// Forgets the messages shown on a destroyed effect instance
+pub(crate) fn forget_path_messages(instance: usize) {
+	showing().retain(|&(key, _), _| key != instance);
+}
+
+/// Whether a file path parameter picks an existing file or a file to write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileMode {
+	Open,
+	Save,
+}
+
+/// Why a path was rejected by a `PathPolicy`
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathViolation {
+	Missing(PathBuf),
+	Extension(PathBuf, Vec<String>),
+}
+
+impl fmt::Display for PathViolation {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			PathViolation::Missing(path) => write!(f, "File not found: {}", path.display()),
+			PathViolation::Extension(path, extensions) => write!(
+				f,
+				"Unsupported file type: {} (expected {})",
+				path.display(),
+				extensions.join(", ")
+			),
+		}
+	}
+}
+
+/// Checks applied to the value of a file or directory path parameter.
+/// An empty path always passes, meaning nothing has been picked yet.
+#[derive(Debug)]
+pub struct PathPolicy {
+	must_exist: bool,
+	extensions: Vec<String>,
+	// tells apart the messages of policies enforced on the same instance
+	id: usize,
+}
+
+impl Default for PathPolicy {
+	fn default() -> Self {
+		PathPolicy {
+			must_exist: false,
+			extensions: Vec::new(),
+			id: NEXT_POLICY.fetch_add(1, Ordering::Relaxed),
+		}
+	}
+}
+
+// a copy checks another parameter, so it gets its own messages
+impl Clone for PathPolicy {
+	fn clone(&self) -> Self {
+		PathPolicy {
+			must_exist: self.must_exist,
+			extensions: self.extensions.clone(),
+			..PathPolicy::default()
+		}
+	}
+}
+
+impl PathPolicy {
+	pub fn new() -> Self {
+		PathPolicy::default()
+	}
+
+	pub fn must_exist(mut self) -> Self {
+		self.must_exist = true;
+		self
+	}
+
+	/// Accepted extensions without the dot, compared case-insensitively
+	pub fn extensions(mut self, extensions: &[&str]) -> Self {
+		self.extensions = extensions
+			.iter()
+			.map(|extension| extension.trim_start_matches('.').to_lowercase())
+			.collect();
+		self
+	}
+
+	pub fn check(&self, path: &Path) -> std::result::Result<(), PathViolation> {
+		if path.as_os_str().is_empty() {
+			return Ok(());
+		}
+		if !self.extensions.is_empty() {
+			let extension = path
+				.extension()
+				.and_then(|extension| extension.to_str())
+				.map(|extension| extension.to_lowercase());
+			let accepted = extension.is_some_and(|extension| self.extensions.contains(&extension));
+			if !accepted {
+				return Err(PathViolation::Extension(
+					path.to_owned(),
+					self.extensions.clone(),
+				));
+			}
+		}
+		if self.must_exist && !path.exists() {
+			return Err(PathViolation::Missing(path.to_owned()));
+		}
+		Ok(())
+	}
+
+	/// Checks `path` and shows a violation as a persistent error on `effect`,
+	/// clearing the message again once the path is valid, unless the message
+	/// was not set by this policy. Hosts without persistent messages get a
+	/// message box once, until the path is valid again. Returns whether the
+	/// path passed.
+	pub fn enforce(&self, effect: &ImageEffectHandle, path: &Path) -> Result<bool> {
+		let key = (effect.key(), self.id);
+		match self.check(path) {
+			Ok(()) => {
+				let shown = showing().remove(&key).is_some();
+				if shown {
+					effect.clear_persistent_message()?;
+				}
+				Ok(true)
+			}
+			Err(violation) => {
+				let text = violation.to_string();
+				if should_post(key, &text, effect.has_persistent_messages()) {
+					effect.set_persistent_message(MessageType::Error, &text)?;
+					showing().insert(key, text);
+				}
+				Ok(false)
+			}
+		}
+	}
+}
+
+mod tests {
+	use super::*;
+
+	#[test]
+	fn extension_filter() {
+		let policy = PathPolicy::new().extensions(&["cube", ".3DL"]);
+		assert!(policy.check(Path::new("")).is_ok());
+		assert!(policy.check(Path::new("/luts/look.CUBE")).is_ok());
+		assert!(policy.check(Path::new("/luts/look.3dl")).is_ok());
+		assert_eq!(
+			policy.check(Path::new("/luts/look")),
+			Err(PathViolation::Extension(
+				PathBuf::from("/luts/look"),
+				vec!["cube".to_owned(), "3dl".to_owned()]
+			))
+		);
+	}
+
+	#[test]
+	fn messages_are_posted_once() {
+		let key = (0x7e57, PathPolicy::new().id);
+		assert!(should_post(key, "missing", false));
+		showing().insert(key, "missing".to_owned());
+		assert!(!should_post(key, "missing", true));
+		assert!(!should_post(key, "wrong type", false));
+		assert!(should_post(key, "wrong type", true));
+		forget_path_messages(0x7e57);
+		assert!(should_post(key, "missing", false));
+		assert_ne!(PathPolicy::new().id, PathPolicy::new().clone().id);
+	}
+
+	#[test]
+	fn must_exist() {
+		let policy = PathPolicy::new().must_exist();
+		assert!(policy.check(Path::new(env!("CARGO_MANIFEST_DIR"))).is_ok());
+		assert!(policy
+			.check(Path::new("/this/path/does/not/exist.cube"))
+			.is_err());
+	}
+}
@@ -1,17 +1,27 @@
 #![feature(plugin)]
 
 use action::*;
+use capabilities::*;
 use enums::*;
 use handle::*;
+use image::*;
+use kernel::*;
+use logger::*;
+use memory::*;
 use ofx_sys::*;
+use path::forget_path_messages;
 use property::*;
 use result::*;
 use std::collections::HashMap;
 use std::ffi::{CStr, CString};
 use std::fmt;
 use std::fmt::Display;
+use std::ops::Range;
 use std::rc::Rc;
+use std::sync::Arc;
 use suites::*;
+use thread::*;
+use tile::*;
 use types::*;
 
 pub struct ApiVersion(pub Int);
@@ -80,10 +90,12 @@ pub trait Plugin: Dispatch + MapAction + Execute {
 
 pub struct PluginDescriptor {
 	plugin_id: CString,
+	log_name: Arc<str>, // plugin_id as shared by every LogScope
 	module_name: String,
 	plugin_index: usize,
 	host: Option<OfxHost>,
 	suites: Option<Suites>,
+	capabilities: Option<Rc<HostCapabilities>>,
 	cached_handle: Option<ImageEffectHandle>,
 	instance: Box<dyn Execute>,
 	global_action_index: EnumIndex<GlobalAction>,
@@ -94,6 +106,8 @@ pub struct PluginDescriptor {
 pub struct PluginContext {
 	host: ImageEffectHost,
 	suites: Suites,
+	capabilities: Rc<HostCapabilities>,
+	abort: Option<AbortPoll>,
 }
 
 pub trait Runnable: Sized + Send + Sync {
@@ -112,12 +126,127 @@ impl PluginContext {
 		self.host.clone()
 	}
 
+	/// What the host supports, as read when the plugin was loaded
+	pub fn capabilities(&self) -> &HostCapabilities {
+		&self.capabilities
+	}
+
+	/// A context whose `parallel_*` and image kernel calls poll `effect` for
+	/// abort before every work item, failing with `Error::Aborted`
+	pub fn abortable(&self, effect: &ImageEffectHandle) -> Result<PluginContext> {
+		Ok(PluginContext {
+			host: self.host.clone(),
+			suites: self.suites.clone(),
+			capabilities: self.capabilities.clone(),
+			abort: Some(effect.abort_poll()?),
+		})
+	}
+
 	pub fn num_threads(&self) -> Result<u32> {
 		let mut c_num_threads: UnsignedInt = 0;
 		to_result! { suite_call!(multiThreadNumCPUs in self.suites.multi_thread; &mut c_num_threads as *mut UnsignedInt)
 		=> c_num_threads}
 	}
 
+	/// Moves `value` into memory allocated by the host
+	pub fn host_box<T>(&self, value: T) -> Result<HostBox<T>> {
+		HostBox::new(*self.suites.memory(), value)
+	}
+
+	/// An empty vector whose storage is allocated by the host
+	pub fn host_vec<T>(&self, capacity: usize) -> Result<HostVec<T>> {
+		HostVec::with_capacity(*self.suites.memory(), capacity)
+	}
+
+	/// A mutex from the host's multi-thread suite protecting `value`
+	pub fn host_mutex<T>(&self, value: T) -> Result<HostMutex<T>> {
+		HostMutex::new(*self.suites.multi_thread, value)
+	}
+
+	/// Index of the calling thread within a `run_in_threads` call
+	pub fn thread_index(&self) -> Result<u32> {
+		let mut index: UnsignedInt = 0;
+		to_result! { suite_call!(multiThreadIndex in self.suites.multi_thread; &mut index as *mut UnsignedInt)
+		=> index}
+	}
+
+	/// Whether the calling thread was spawned by `run_in_threads`
+	pub fn is_spawned_thread(&self) -> Result<bool> {
+		Ok(suite_call!(multiThreadIsSpawnedThread in self.suites.multi_thread;) != 0)
+	}
+
+	/// Runs `body` for every index of `range` on the host's threads. Each
+	/// thread starts on its own share of the range and steals from the others
+	/// once done, so uneven work still balances. Stops at the first error and
+	/// returns it; a panic in `body` is resumed here.
+	pub fn parallel_for<F>(&self, range: Range<usize>, body: F) -> Result<()>
+	where
+		F: Fn(usize) -> Result<()> + Sync,
+	{
+		parallel_for(&self.suites.multi_thread, self.abort, range, &body)
+	}
+
+	/// Like `parallel_for` over the rows of `image`, passing each row's `y`
+	/// and pixels to `body`.
+	pub fn parallel_rows<T, F>(&self, image: &mut ImageDescriptorMut<T>, body: F) -> Result<()>
+	where
+		T: PixelFormat + Send,
+		F: Fn(Int, &mut [T]) -> Result<()> + Sync,
+	{
+		parallel_rows(&self.suites.multi_thread, self.abort, image, &body)
+	}
+
+	/// Like `parallel_for` over the tiles of `grid`, passing each tile's
+	/// rectangle and pixels to `body`. The grid must lie within `image`,
+	/// see `TileGrid::new`.
+	pub fn parallel_tiles<T, F>(
+		&self,
+		image: &mut ImageDescriptorMut<T>,
+		grid: &TileGrid,
+		body: F,
+	) -> Result<()>
+	where
+		T: PixelFormat + Send,
+		F: Fn(RectI, ImageBufferMut<T>) -> Result<()> + Sync,
+	{
+		parallel_tiles(&self.suites.multi_thread, self.abort, image, grid, &body)
+	}
+
+	/// Runs `kernel` on the host's threads over `render_window`, writing to
+	/// `dst`; pixels `src` does not cover are written transparent black. The
+	/// kernel is picked for the format of `dst`, see `dispatch_format`; `src`
+	/// must have the same format.
+	pub fn map_image<K>(&self, kernel: &K, src: &Image, dst: &mut Image, render_window: RectI) -> Result<()>
+	where
+		K: MapKernel,
+	{
+		map_image(&self.suites.multi_thread, self.abort, kernel, src, dst, render_window)
+	}
+
+	/// Like `map_image`, also reading an alpha `mask` of any depth, which the
+	/// kernel sees converted to the depth of `dst`
+	pub fn zip_image<K>(
+		&self,
+		kernel: &K,
+		src: &Image,
+		mask: &Image,
+		dst: &mut Image,
+		render_window: RectI,
+	) -> Result<()>
+	where
+		K: ZipKernel,
+	{
+		zip_image(&self.suites.multi_thread, self.abort, kernel, src, mask, dst, render_window)
+	}
+
+	/// Runs `kernel` on the host's threads over `render_window`, writing to `dst`
+	pub fn generate_image<K>(&self, kernel: &K, dst: &mut Image, render_window: RectI) -> Result<()>
+	where
+		K: GenerateKernel,
+	{
+		generate_image(&self.suites.multi_thread, self.abort, kernel, dst, render_window)
+	}
+
 	fn run_in_threads_internal(
 		&self,
 		function: ThreadFunction,
@@ -248,7 +377,11 @@ impl Filter for PluginDescriptor {
 		_status: Result<Int>,
 	) -> Result<Int> {
 		match action {
-			Action::DestroyInstance(ref mut effect) => effect.drop_instance_data(),
+			Action::DestroyInstance(ref mut effect) => {
+				effect.clear_log_context();
+				forget_path_messages(effect.key());
+				effect.drop_instance_data()
+			}
 			_ => Ok(()),
 		}?;
 
@@ -269,15 +402,20 @@ impl Dispatch for PluginDescriptor {
 				in_args,
 				out_args,
 			} => {
+				let _scope = LogScope::new(self.log_name.clone(), handle).enter();
 				let mut mapped_action = self.map_action(action, handle, in_args, out_args)?;
 
 				debug!("Mapped action found: {:?}", mapped_action);
 				self.before_execute(&mapped_action)?;
 
-				if let (Some(host), Some(suites)) = (self.host, self.suites.clone()) {
+				if let (Some(host), Some(suites), Some(capabilities)) =
+					(self.host, self.suites.clone(), self.capabilities.clone())
+				{
 					let plugin_context = PluginContext {
 						host: ImageEffectHost::new(host.host, suites.property()),
 						suites,
+						capabilities,
+						abort: None,
 					};
 					let status = self.execute(&plugin_context, &mut mapped_action);
 					self.after_execute(&plugin_context, &mut mapped_action, status)?;
@@ -385,10 +523,12 @@ impl PluginDescriptor {
 		PluginDescriptor {
 			plugin_index,
 			module_name: module_name.to_owned(),
+			log_name: name.into(),
 			plugin_id,
 			instance,
 			host: None,
 			suites: None,
+			capabilities: None,
 			cached_handle: None,
 			global_action_index,
 			image_effect_action_index,
@@ -416,6 +556,11 @@ impl PluginDescriptor {
 			image_effect_suite,
 			image_effect_opengl_render,
 			parameter_suite,
+			suites.message(),
+			suites.message_v2(),
+			suites.progress(),
+			suites.progress_v2(),
+			suites.time_line(),
 		))
 	}
 
@@ -477,6 +622,12 @@ impl PluginDescriptor {
 			fetch_suite!(ParametricParameter, V1),
 			fetch_suite!(ImageEffectOpenGLRender, V1),
 		));
+		let suites = self.suites()?;
+		attach_log_host(*suites.message());
+		let capabilities =
+			HostCapabilities::new(&ImageEffectHost::new(host.host, suites.property()), suites);
+		debug!("Host capabilities {:?}", capabilities);
+		self.capabilities = Some(Rc::new(capabilities));
 		info!("Loaded plugin");
 		OK
 	}
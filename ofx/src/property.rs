@@ -1,7 +1,7 @@
 use enums::{
-	BitDepth, Change, HostNativeOrigin, IdentifiedEnum, ImageComponent, ImageEffectContext,
-	ImageEffectRender, ImageField, ImageFieldExtraction, ImageFieldOrder, ParamDoubleType,
-	ParamStringType,
+	BitDepth, Change, ChoiceEnum, HostNativeOrigin, IdentifiedEnum, ImageComponent,
+	ImageEffectContext, ImageEffectRender, ImageField, ImageFieldExtraction, ImageFieldOrder,
+	ImageType, ParamDoubleType, ParamInvalidate, ParamStringType, ParamType,
 	Type as EType,
 };
 use handle::Image;
@@ -343,7 +343,7 @@ where
 	fn properties(&self) -> Result<T>;
 }
 
-pub trait Readable: AsProperties + Sized + Clone {
+pub trait Readable: AsProperties + Sized {
 	fn get<P>(&self) -> Result<P::ReturnType>
 	where
 		P: Named + Get,
@@ -359,6 +359,26 @@ pub trait Readable: AsProperties + Sized + Clone {
 	{
 		<P::ReturnType as Getter<Self, P>>::get_at(self, index)
 	}
+
+	/// The number of values a multi-dimensional property holds
+	fn dimension<P>(&self) -> Result<usize>
+	where
+		P: Named,
+	{
+		let mut dimension: Int = 0;
+		to_result! { suite_call!(propGetDimension in *self.suite(); self.handle(), P::name().as_ptr() as CharPtr, &mut dimension as *mut Int)
+		=> dimension.max(0) as usize }
+	}
+
+	fn get_all<P>(&self) -> Result<Vec<P::ReturnType>>
+	where
+		P: Named + Get,
+		P::ReturnType: ValueType + Sized + Getter<Self, P>,
+	{
+		(0..self.dimension::<P>()?)
+			.map(|index| self.get_at::<P>(index))
+			.collect()
+	}
 }
 
 pub trait RawReadable: AsProperties + Sized + Clone {
@@ -422,7 +442,7 @@ pub trait RawWritable: AsProperties + Sized + Clone {
 	}
 }
 
-impl<R> Readable for R where R: AsProperties + Clone {}
+impl<R> Readable for R where R: AsProperties {}
 
 impl<W> Writable for W where W: AsProperties + ?Sized + Clone {}
 
@@ -689,6 +709,12 @@ mod tests {
 			self.set::<DummyProperty>(value)
 		}
 	}
+
+	#[test]
+	fn persistence_property_name() {
+		// the misspelling is the one in the OFX headers
+		assert_eq!(Persistent::Property::name(), b"OfxParamPropPersistant\0");
+	}
 }
 
 property! { kOfxPluginPropFilePath as FilePath {
@@ -719,8 +745,9 @@ property! { kOfxPropLongLabel as LongLabel {
 	set_long_label(&str);
 }}
 
+// the first of the version numbers, `get_all` reads them all
 property! { kOfxPropVersion as Version {
-	get_version() -> String;
+	get_version() -> Int;
 }}
 
 property! { kOfxPropVersionLabel as VersionLabel {
@@ -785,6 +812,14 @@ property! { kOfxParamHostPropSupportsParametricAnimation as SupportsParametricAn
 	get_supports_parametric_animation() -> Bool;
 }}
 
+property! { kOfxParamHostPropSupportsStrChoice as SupportsStrChoice {
+	get_supports_str_choice() -> Bool;
+}}
+
+property! { kOfxParamHostPropSupportsStrChoiceAnimation as SupportsStrChoiceAnimation {
+	get_supports_str_choice_animation() -> Bool;
+}}
+
 property! { kOfxParamHostPropMaxParameters as MaxParameters {
 	get_max_parameters() -> Int;
 }}
@@ -793,8 +828,9 @@ property! { kOfxParamHostPropMaxPages as MaxPages {
 	get_max_pages() -> Int;
 }}
 
+// rows in `x`, columns in `y`
 property! { kOfxParamHostPropPageRowColumnCount as PageRowColumnCount {
-	get_page_row_column_count() -> RectI;
+	get_page_row_column_count() -> PointI;
 }}
 
 property! { kOfxImageEffectPluginPropGrouping as Grouping {
@@ -913,8 +949,8 @@ property! { kOfxImageEffectPropSupportedComponents as SupportedComponents {
 }}
 
 property! { kOfxImageEffectPropPreMultiplication as PreMultiplication {
-	get_pre_multiplication() -> Bool;
-	set_pre_multiplication(Bool);
+	get_pre_multiplication() -> CString as enum ImageType;
+	set_pre_multiplication(&[u8] as enum ImageType);
 }}
 
 property! { kOfxImageEffectPropRenderWindow as RenderWindow {
@@ -1113,6 +1149,10 @@ property! { kOfxPropParamSetNeedsSyncing as NeedsSyncing {
 	get_needs_syncing() -> Bool;
 }}
 
+property! { kOfxParamPropType as ParamTypeProp {
+	get_param_type() -> CString as enum ParamType;
+}}
+
 property! { kOfxParamPropHint as Hint {
 	get_hint() -> String;
 	set_hint(&str);
@@ -1137,13 +1177,53 @@ property! { kOfxParamPropGroupOpen as GroupOpen {
 	get_group_open() -> Bool;
 	set_group_open(Bool);
 }}
-property! { kOfxParamPropPersistent as Persistent {
+property! { kFnOfxParamPropGroupIsTab as GroupIsTab {
+	get_group_is_tab() -> Bool;
+	set_group_is_tab(Bool);
+}}
+
+property! { kOfxParamPropPersistant as Persistent {
 	get_persistent() -> Bool;
 	set_persistent(Bool);
 }}
 
+property! { kOfxParamPropEvaluateOnChange as EvaluateOnChange {
+	get_evaluate_on_change() -> Bool;
+	set_evaluate_on_change(Bool);
+}}
+
+property! { kOfxParamPropCacheInvalidation as CacheInvalidation {
+	get_cache_invalidation() -> CString as enum ParamInvalidate;
+	set_cache_invalidation(&[u8] as enum ParamInvalidate);
+}}
+
+property! { kOfxParamPropAnimates as Animates {
+	get_animates() -> Bool;
+	set_animates(Bool);
+}}
+
+property! { kOfxParamPropCanUndo as CanUndo {
+	get_can_undo() -> Bool;
+	set_can_undo(Bool);
+}}
+
+property! { kOfxParamPropIsAutoKeying as IsAutoKeying {
+	get_is_auto_keying() -> Bool;
+}}
+
+property! { kOfxParamPropPluginMayWrite as PluginMayWrite {
+	get_plugin_may_write() -> Bool;
+	set_plugin_may_write(Bool);
+}}
+
+property! { kOfxParamPropDataPtr as DataPtr {
+	get_data_ptr() -> VoidPtrMut;
+	set_data_ptr(VoidPtr);
+}}
+
 property_group! { CommonParameters {
 	TypeProp			read,
+	ParamTypeProp		read,
 	Label				read+write,
 	Hint				read+write,
 	Parent				read+write,
@@ -1151,16 +1231,38 @@ property_group! { CommonParameters {
 	Enabled				read+write,
 	Secret				read+write,
 	Persistent			read+write,
+	DataPtr				read+write,
+}}
+
+// flags of parameters that hold a value, as opposed to groups, pages and buttons
+property_group! { ValueParameters {
+	IsAutoKeying		read,
+	EvaluateOnChange	read+write,
+	CacheInvalidation	read+write,
+	Animates			read+write,
+	CanUndo				read+write,
+	PluginMayWrite		read+write,
 }}
 
 pub mod double {
 	use super::*;
 	property_assign_name!(kOfxParamPropDoubleType as DoubleType: (&[u8]) -> CString);
 	property_assign_name!(kOfxParamPropDefault as Default: Double);
+	property_assign_name!(kOfxParamPropMax as Max: Double);
+	property_assign_name!(kOfxParamPropMin as Min: Double);
 	property_assign_name!(kOfxParamPropDisplayMax as DisplayMax: Double);
 	property_assign_name!(kOfxParamPropDisplayMin as DisplayMin: Double);
 }
 
+pub mod int {
+	use super::*;
+	property_assign_name!(kOfxParamPropDefault as Default: Int);
+	property_assign_name!(kOfxParamPropMax as Max: Int);
+	property_assign_name!(kOfxParamPropMin as Min: Int);
+	property_assign_name!(kOfxParamPropDisplayMax as DisplayMax: Int);
+	property_assign_name!(kOfxParamPropDisplayMin as DisplayMin: Int);
+}
+
 pub mod boolean {
 	use super::*;
 	property_assign_name!(kOfxParamPropDefault as Default: Bool);
@@ -1169,12 +1271,25 @@ pub mod boolean {
 pub mod string {
 	use super::*;
 	property_assign_name!(kOfxParamPropStringMode as StringType: (&[u8]) -> CString);
+	property_assign_name!(kOfxParamPropDefault as Default: (&str) -> String);
+	property_assign_name!(kOfxParamPropStringFilePathExists as FilePathExists: Bool);
 }
 
 pub mod choice {
 	use super::*;
 	property_assign_name!(kOfxParamPropDefault as Default: Int);
 	property_assign_name!(kOfxParamPropChoiceOption as ChoiceOption: (&str) -> String);
+	property_assign_name!(kOfxParamPropChoiceOrder as ChoiceOrder: Int);
+	property_assign_name!(kOfxParamPropChoiceLabelOption as ChoiceLabelOption: (&str) -> String);
+	property_assign_name!(kNatronOfxParamPropChoiceHostCanAddOptions as HostCanAddOptions: Bool);
+}
+
+pub mod str_choice {
+	use super::*;
+	property_assign_name!(kOfxParamPropDefault as Default: (&str) -> String);
+	property_assign_name!(kOfxParamPropChoiceOption as ChoiceOption: (&str) -> String);
+	property_assign_name!(kOfxParamPropChoiceEnum as ChoiceEnum: (&str) -> String);
+	property_assign_name!(kOfxParamPropChoiceLabelOption as ChoiceLabelOption: (&str) -> String);
 }
 
 pub mod page {
@@ -1228,6 +1343,8 @@ pub mod DoubleParams {
 	pub trait CanSet: Writable {
 		property_define_setter_trait!(set_double_type, double::DoubleType, enum ParamDoubleType);
 		property_define_setter_trait!(set_default, double::Default);
+		property_define_setter_trait!(set_max, double::Max);
+		property_define_setter_trait!(set_min, double::Min);
 		property_define_setter_trait!(set_display_max, double::DisplayMax);
 		property_define_setter_trait!(set_display_min, double::DisplayMin);
 	}
@@ -1235,6 +1352,20 @@ pub mod DoubleParams {
 
 pub use DoubleParams::CanSet as CanSetDoubleParams;
 
+#[allow(non_snake_case)]
+pub mod IntParams {
+	use super::*;
+	pub trait CanSet: Writable {
+		property_define_setter_trait!(set_default, int::Default);
+		property_define_setter_trait!(set_max, int::Max);
+		property_define_setter_trait!(set_min, int::Min);
+		property_define_setter_trait!(set_display_max, int::DisplayMax);
+		property_define_setter_trait!(set_display_min, int::DisplayMin);
+	}
+}
+
+pub use IntParams::CanSet as CanSetIntParams;
+
 #[allow(non_snake_case)]
 pub mod BooleanParams {
 	use super::*;
@@ -1250,6 +1381,8 @@ pub mod StringParams {
 	use super::*;
 	pub trait CanSet: Writable {
 		property_define_setter_trait!(set_string_type, string::StringType, enum ParamStringType);
+		property_define_setter_trait!(set_default, &string::Default);
+		property_define_setter_trait!(set_file_path_exists, string::FilePathExists);
 	}
 }
 
@@ -1260,12 +1393,72 @@ pub mod ChoiceParams {
 	use super::*;
 	pub trait CanSet: Writable {
 		property_define_setter_trait!(set_default, choice::Default);
+
+		fn set_default_choice<E>(&mut self, value: E) -> Result<()>
+		where
+			E: ChoiceEnum,
+		{
+			self.set_default(value.index())
+		}
+
+		// Natron extension letting users add options of their own, the value is
+		// then the option string and typed handles match it against labels
+		property_define_setter_trait!(set_host_can_add_options, choice::HostCanAddOptions);
+
+		/// Sets options, per-option hints and display order from a `ChoiceEnum`.
+		/// Hints and order are extensions, hosts not supporting them keep
+		/// showing the options in index order. Retired indices show as "-".
+		fn set_choice_enum<E>(&mut self) -> Result<()>
+		where
+			E: ChoiceEnum,
+		{
+			let options = E::options_by_index()?;
+			for (index, (order, option)) in options.iter().enumerate() {
+				// retired indices keep their slot so later options do not move
+				let (label, hint) = option.map_or(("-", ""), |option| (option.label, option.hint));
+				self.set_at::<choice::ChoiceOption>(index, label)?;
+				self.set_at::<choice::ChoiceOrder>(index, order).ok();
+				self.set_at::<choice::ChoiceLabelOption>(index, hint).ok();
+			}
+			self.set_default_choice(E::default())
+		}
 	}
 }
 
 pub use ChoiceParams::CanSet as CanSetChoiceParams;
 
+#[allow(non_snake_case)]
+pub mod StrChoiceParams {
+	use super::*;
+	pub trait CanSet: Writable {
+		property_define_setter_trait!(set_default, &str_choice::Default);
+
+		fn set_default_choice<E>(&mut self, value: E) -> Result<()>
+		where
+			E: ChoiceEnum,
+		{
+			self.set_default(value.name())
+		}
+
+		/// Sets labels, stored names and hints from a `ChoiceEnum`, in display order.
+		fn set_choice_enum<E>(&mut self) -> Result<()>
+		where
+			E: ChoiceEnum,
+		{
+			for (index, option) in E::options().iter().enumerate() {
+				self.set_at::<str_choice::ChoiceOption>(index, option.label)?;
+				self.set_at::<str_choice::ChoiceEnum>(index, option.name)?;
+				self.set_at::<str_choice::ChoiceLabelOption>(index, option.hint).ok();
+			}
+			self.set_default_choice(E::default())
+		}
+	}
+}
+
+pub use StrChoiceParams::CanSet as CanSetStrChoiceParams;
+
 impl<T> CommonParameters for ParamHandle<T> where T: ParamHandleValue + Clone {}
+impl<T> ValueParameters for ParamHandle<T> where T: ParamHandleValue + Clone {}
 
 // https://openfx.readthedocs.io/en/doc/Reference/ofxPropertiesByObject.html#properties-on-an-effect-descriptor
 object_properties! { ImageEffectHost {
@@ -1280,6 +1473,7 @@ object_properties! { ImageEffectHost {
 	TemporalClipAccess			read,
 	SupportedComponents			read,
 	SupportedContexts			read,
+	SupportedPixelDepths		read,
 	SupportsMultipleClipDepths	read,
 	SupportsMultipleClipPARs	read,
 	SetableFrameRate			read,
@@ -1289,6 +1483,8 @@ object_properties! { ImageEffectHost {
 	SupportsChoiceAnimation		read,
 	SupportsBooleanAnimation	read,
 	SupportsCustomAnimation		read,
+	SupportsStrChoice			read,
+	SupportsStrChoiceAnimation	read,
 	MaxParameters				read,
 	MaxPages					read,
 	PageRowColumnCount			read,
@@ -1418,25 +1614,41 @@ object_properties! { Image {
 
 object_properties! { ParamDouble {
 	CommonParameters			inherit,
+	ValueParameters				inherit,
 	DoubleParams				write,
 }}
 
+object_properties! { ParamInt {
+	CommonParameters			inherit,
+	ValueParameters				inherit,
+	IntParams					write,
+}}
+
 object_properties! { ParamBoolean {
 	CommonParameters			inherit,
+	ValueParameters				inherit,
 	BooleanParams				write,
 }}
 
 object_properties! { ParamString {
 	CommonParameters			inherit,
+	ValueParameters				inherit,
 	StringParams				write,
 }}
 
 object_properties! { ParamChoice {
 	CommonParameters			inherit,
+	ValueParameters				inherit,
 	Choices						write,
 	ChoiceParams				write,
 }}
 
+object_properties! { ParamStrChoice {
+	CommonParameters			inherit,
+	ValueParameters				inherit,
+	StrChoiceParams				write,
+}}
+
 object_properties! { ParamPage {
 	CommonParameters			inherit,
 	Children					write,
@@ -1445,10 +1657,16 @@ object_properties! { ParamPage {
 object_properties! { ParamGroup {
 	CommonParameters			inherit,
 	GroupOpen                   write,
+	GroupIsTab					write,
+}}
+
+object_properties! { ParamDescriptor {
+	CommonParameters			inherit,
 }}
 
 object_properties! { ParamPushButton {
 	CommonParameters			inherit,
+	EvaluateOnChange			read+write,
 }}
 
 object_properties! { ParameterSet {
@@ -1491,6 +1709,7 @@ object_properties! { GetRegionsOfInterestOutArgs {
 
 object_properties! { GetClipPreferencesOutArgs {
 	RawWritable					inherit,
+	PreMultiplication			write,
 }}
 
 object_properties! { InstanceChangedInArgs {
@@ -25,6 +25,10 @@ pub enum Error {
 	HostNotReady,
 	EnumNotFound,
 	SuiteNotInitialized,
+	InvalidLayout,
+	Aborted,
+	Unsupported,
+	LoggerAlreadySet,
 	Unimplemented,
 	UnknownError,
 }
@@ -40,6 +44,7 @@ impl From<OfxStatus> for Error {
 			ofx_sys::eOfxStatus_ErrBadHandle => Error::InvalidHandle,
 			ofx_sys::eOfxStatus_ErrBadIndex => Error::InvalidIndex,
 			ofx_sys::eOfxStatus_ErrValue => Error::InvalidValue,
+			ofx_sys::eOfxStatus_ErrUnsupported => Error::Unsupported,
 			_ => Error::UnknownError,
 		}
 	}
@@ -0,0 +1,340 @@
+use coords::*;
+use enums::*;
+use image::*;
+use types::*;
+
+/// How a `Sampler` reconstructs values between pixel centres
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+	Nearest,
+	Bilinear,
+	/// Catmull-Rom
+	Bicubic,
+	/// Three lobed Lanczos
+	Lanczos,
+}
+
+/// What a `Sampler` reads outside of the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeMode {
+	/// Transparent black
+	Black,
+	Clamp,
+	Repeat,
+	Mirror,
+}
+
+const MAX_TAPS: usize = 6;
+
+fn catmull_rom(x: f32) -> f32 {
+	let x = x.abs();
+	if x < 1.0 {
+		(1.5 * x - 2.5) * x * x + 1.0
+	} else if x < 2.0 {
+		((-0.5 * x + 2.5) * x - 4.0) * x + 2.0
+	} else {
+		0.0
+	}
+}
+
+fn lanczos3(x: f32) -> f32 {
+	let x = x.abs();
+	if x < 1e-6 {
+		1.0
+	} else if x < 3.0 {
+		let pi_x = std::f32::consts::PI * x;
+		3.0 * pi_x.sin() * (pi_x / 3.0).sin() / (pi_x * pi_x)
+	} else {
+		0.0
+	}
+}
+
+impl Interpolation {
+	// First pixel and weights of the taps around `position` on one axis,
+	// with pixel centres at half integers.
+	fn taps(self, position: f64) -> (Int, usize, [f32; MAX_TAPS]) {
+		let mut weights = [0.0; MAX_TAPS];
+		if self == Interpolation::Nearest {
+			weights[0] = 1.0;
+			return (position.floor() as Int, 1, weights);
+		}
+		let centre = position - 0.5;
+		let base = centre.floor();
+		let t = (centre - base) as f32;
+		let base = base as Int;
+		let (first, count, kernel): (Int, usize, fn(f32) -> f32) = match self {
+			Interpolation::Bilinear => (0, 2, |x| (1.0 - x.abs()).max(0.0)),
+			Interpolation::Bicubic => (-1, 4, catmull_rom),
+			_ => (-2, 6, lanczos3),
+		};
+		let mut sum = 0.0;
+		for (tap, weight) in weights.iter_mut().take(count).enumerate() {
+			*weight = kernel(t - (first + tap as Int) as f32);
+			sum += *weight;
+		}
+		// Lanczos does not quite add up to one
+		for weight in weights.iter_mut().take(count) {
+			*weight /= sum;
+		}
+		(base + first, count, weights)
+	}
+}
+
+impl EdgeMode {
+	// maps `i` into `start..end`, or None for black and for empty ranges
+	pub(crate) fn wrap(self, i: Int, start: Int, end: Int) -> Option<Int> {
+		if i >= start && i < end {
+			return Some(i);
+		}
+		let size = end - start;
+		if size <= 0 {
+			return None;
+		}
+		match self {
+			EdgeMode::Black => None,
+			EdgeMode::Clamp => Some(i.max(start).min(end - 1)),
+			EdgeMode::Repeat => Some(start + (i - start).rem_euclid(size)),
+			EdgeMode::Mirror => {
+				let m = (i - start).rem_euclid(2 * size);
+				Some(start + if m < size { m } else { 2 * size - 1 - m })
+			}
+		}
+	}
+}
+
+// the channels of `pixel` in RGBA order; missing colour is black and
+// missing alpha opaque, YUVA keeps its channel order
+fn to_rgba<T>(pixel: &T) -> [f32; 4]
+where
+	T: PixelFormat,
+{
+	let c = |i| pixel.channel(i).to_f32();
+	match T::num_components() {
+		1 => [0.0, 0.0, 0.0, c(0)],
+		3 => [c(0), c(1), c(2), 1.0],
+		_ => [c(0), c(1), c(2), c(3)],
+	}
+}
+
+/// Reads an image at arbitrary positions, filtered, as `RGBAColourF` whatever
+/// the storage depth. Alpha images sample into `a`, RGB images are opaque.
+/// Only pixels inside both the image bounds and the region of definition are
+/// read; the edge mode decides what lies beyond.
+#[derive(Clone)]
+pub struct Sampler<'a, T>
+where
+	T: PixelFormat,
+{
+	image: ImageBuffer<'a, T>,
+	interpolation: Interpolation,
+	edge: EdgeMode,
+	coordinates: CoordinateSystem,
+	region_of_definition: Option<RectD>,
+	region: RectI,
+}
+
+impl<'a, T> Sampler<'a, T>
+where
+	T: PixelFormat,
+{
+	/// A bilinear sampler, black outside the image, in pixel coordinates
+	pub fn new(image: &ImageDescriptor<'a, T>) -> Self {
+		let image = image.data();
+		Sampler {
+			image,
+			interpolation: Interpolation::Bilinear,
+			edge: EdgeMode::Black,
+			coordinates: CoordinateSystem::default(),
+			region_of_definition: None,
+			region: image.bounds(),
+		}
+	}
+
+	pub fn interpolation(self, interpolation: Interpolation) -> Self {
+		Sampler {
+			interpolation,
+			..self
+		}
+	}
+
+	pub fn edge(self, edge: EdgeMode) -> Self {
+		Sampler { edge, ..self }
+	}
+
+	/// Sets how canonical coordinates map to the image's pixels, for
+	/// `sample_canonical` and the region of definition
+	pub fn coordinates(self, coordinates: CoordinateSystem) -> Self {
+		Sampler {
+			coordinates,
+			..self
+		}
+		.update_region()
+	}
+
+	/// Limits reads to the clip's region of definition, in canonical coordinates
+	pub fn region_of_definition(self, region_of_definition: RectD) -> Self {
+		Sampler {
+			region_of_definition: Some(region_of_definition),
+			..self
+		}
+		.update_region()
+	}
+
+	fn update_region(self) -> Self {
+		let bounds = self.image.bounds();
+		let region = match self.region_of_definition {
+			Some(rod) => {
+				let rod = self.coordinates.canonical_rect_to_pixel(rod);
+				let x1 = bounds.x1.max(rod.x1);
+				let y1 = bounds.y1.max(rod.y1);
+				RectI {
+					x1,
+					y1,
+					x2: bounds.x2.min(rod.x2).max(x1),
+					y2: bounds.y2.min(rod.y2).max(y1),
+				}
+			}
+			None => bounds,
+		};
+		Sampler { region, ..self }
+	}
+
+	/// The pixels samples are read from
+	pub fn region(&self) -> RectI {
+		self.region
+	}
+
+	fn fetch(&self, x: Int, y: Int) -> Option<[f32; 4]> {
+		let r = self.region;
+		let x = self.edge.wrap(x, r.x1, r.x2)?;
+		let y = self.edge.wrap(y, r.y1, r.y2)?;
+		Some(to_rgba(&self.image.row(y)[(x - self.image.bounds().x1) as usize]))
+	}
+
+	/// The value at (`x`, `y`) in pixel coordinates, where pixel (i, j)
+	/// covers `i..i + 1` and `j..j + 1`
+	pub fn sample(&self, x: Double, y: Double) -> RGBAColourF {
+		let mut sum = [0.0f32; 4];
+		if self.region.x1 < self.region.x2 && self.region.y1 < self.region.y2 {
+			let (x0, columns, x_weights) = self.interpolation.taps(x);
+			let (y0, rows, y_weights) = self.interpolation.taps(y);
+			for (j, y_weight) in y_weights.iter().take(rows).enumerate() {
+				for (i, x_weight) in x_weights.iter().take(columns).enumerate() {
+					if let Some(pixel) = self.fetch(x0 + i as Int, y0 + j as Int) {
+						let weight = x_weight * y_weight;
+						for (sum, value) in sum.iter_mut().zip(pixel.iter()) {
+							*sum += weight * value;
+						}
+					}
+				}
+			}
+		}
+		RGBAColourF {
+			r: sum[0],
+			g: sum[1],
+			b: sum[2],
+			a: sum[3],
+		}
+	}
+
+	/// The value at (`x`, `y`) in canonical coordinates, see `coordinates`
+	pub fn sample_canonical(&self, x: Double, y: Double) -> RGBAColourF {
+		let pixel = self.coordinates.canonical_to_pixel(PointD { x, y });
+		self.sample(pixel.x, pixel.y)
+	}
+}
+
+mod tests {
+	use super::*;
+
+	const BOUNDS: RectI = RectI {
+		x1: -2,
+		y1: 0,
+		x2: 2,
+		y2: 2,
+	};
+
+	// alpha ramp of 0, 1, 2, 3 along x, 10 more on the second row
+	fn ramp() -> Vec<f32> {
+		vec![0.0, 1.0, 2.0, 3.0, 10.0, 11.0, 12.0, 13.0]
+	}
+
+	fn sampler(data: &mut [f32]) -> Sampler<'_, f32> {
+		let image = unsafe { ImageDescriptor::new(BOUNDS, 16, data.as_mut_ptr() as VoidPtrMut) };
+		Sampler::new(&image)
+	}
+
+	#[test]
+	fn pixel_centres_are_exact() {
+		let mut data = ramp();
+		for interpolation in &[
+			Interpolation::Nearest,
+			Interpolation::Bilinear,
+			Interpolation::Bicubic,
+			Interpolation::Lanczos,
+		] {
+			let sampler = sampler(&mut data)
+				.interpolation(*interpolation)
+				.edge(EdgeMode::Clamp);
+			assert!((sampler.sample(-0.5, 1.5).a - 11.0).abs() < 1e-4);
+			assert_eq!(sampler.sample(-0.5, 1.5).r, 0.0);
+		}
+	}
+
+	#[test]
+	fn bilinear_blends_neighbours() {
+		let mut data = ramp();
+		let sampler = sampler(&mut data);
+		assert_eq!(sampler.sample(0.0, 0.5).a, 1.5);
+		assert_eq!(sampler.sample(0.0, 1.0).a, 6.5);
+		// half of the outermost pixel blends with black
+		assert_eq!(sampler.sample(-2.0, 0.5).a, 0.0);
+		assert_eq!(sampler.sample(2.0, 0.5).a, 1.5);
+	}
+
+	#[test]
+	fn edge_modes() {
+		assert_eq!(EdgeMode::Black.wrap(4, 0, 4), None);
+		assert_eq!(EdgeMode::Clamp.wrap(-3, 0, 4), Some(0));
+		assert_eq!(EdgeMode::Repeat.wrap(-1, 0, 4), Some(3));
+		assert_eq!(EdgeMode::Repeat.wrap(9, 0, 4), Some(1));
+		assert_eq!(EdgeMode::Mirror.wrap(-1, 0, 4), Some(0));
+		assert_eq!(EdgeMode::Mirror.wrap(5, 0, 4), Some(2));
+		assert_eq!(EdgeMode::Mirror.wrap(8, 0, 4), Some(0));
+		for edge in &[EdgeMode::Clamp, EdgeMode::Repeat, EdgeMode::Mirror] {
+			assert_eq!(edge.wrap(3, 2, 2), None);
+		}
+	}
+
+	#[test]
+	fn canonical_and_region_of_definition() {
+		let mut data = ramp();
+		let sampler = sampler(&mut data)
+			.interpolation(Interpolation::Nearest)
+			.edge(EdgeMode::Clamp)
+			.coordinates(CoordinateSystem::new(PointD { x: 0.5, y: 0.5 }, 2.0))
+			.region_of_definition(RectD {
+				x1: -4.0,
+				y1: 0.0,
+				x2: 4.0,
+				y2: 2.0,
+			});
+		let region = sampler.region();
+		assert_eq!((region.x1, region.y1, region.x2, region.y2), (-1, 0, 1, 1));
+		// canonical x 2 is pixel 0.5 at half scale and a PAR of 2
+		assert_eq!(sampler.sample_canonical(2.0, 0.0).a, 2.0);
+		// clamped to the region of definition rather than the bounds
+		assert_eq!(sampler.sample(1.5, 1.5).a, 2.0);
+	}
+
+	#[test]
+	fn weights_add_up() {
+		for interpolation in &[Interpolation::Bicubic, Interpolation::Lanczos] {
+			for position in &[0.1, 0.5, 0.73, 1.99] {
+				let (_, count, weights) = interpolation.taps(*position);
+				let sum: f32 = weights.iter().take(count).sum();
+				assert!((sum - 1.0).abs() < 1e-5);
+			}
+		}
+	}
+}
@@ -78,4 +78,32 @@ impl Suites {
 	pub fn parameter(&self) -> Rc<OfxParameterSuiteV1> {
 		self.parameter.clone()
 	}
+
+	pub fn memory(&self) -> Rc<OfxMemorySuiteV1> {
+		self.memory.clone()
+	}
+
+	pub fn message(&self) -> Rc<OfxMessageSuiteV1> {
+		self.message.clone()
+	}
+
+	pub fn message_v2(&self) -> Option<Rc<OfxMessageSuiteV2>> {
+		self.message_v2.clone()
+	}
+
+	pub fn progress(&self) -> Rc<OfxProgressSuiteV1> {
+		self.progress.clone()
+	}
+
+	pub fn progress_v2(&self) -> Option<Rc<OfxProgressSuiteV2>> {
+		self.progress_v2.clone()
+	}
+
+	pub fn time_line(&self) -> Rc<OfxTimeLineSuiteV1> {
+		self.time_line.clone()
+	}
+
+	pub fn parametric_parameter(&self) -> Option<Rc<OfxParametricParameterSuiteV1>> {
+		self.parametric_parameter.clone()
+	}
 }
@@ -0,0 +1,547 @@
+use handle::AbortPoll;
+use image::*;
+use logger::LogScope;
+use ofx_sys::*;
+use result::*;
+use std::any::Any;
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut, Range};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use tile::TileGrid;
+use types::*;
+
+/// A mutex created by the host's multi-thread suite, safe to use from the
+/// host's own worker threads. Created with `PluginContext::host_mutex`.
+///
+/// Host mutexes are recursive, so locking again on the thread that holds the
+/// lock fails with `Error::InvalidValue` rather than handing out a second
+/// guard to the same value.
+pub struct HostMutex<T> {
+	multi_thread: OfxMultiThreadSuiteV1,
+	inner: OfxMutexHandle,
+	// `current_thread()` of the lock holder, 0 when unlocked
+	owner: AtomicUsize,
+	value: UnsafeCell<T>,
+}
+
+thread_local!(static THREAD: u8 = const { 0 });
+
+// nonzero and unique among live threads
+fn current_thread() -> usize {
+	THREAD.with(|thread| thread as *const u8 as usize)
+}
+
+unsafe impl<T: Send> Send for HostMutex<T> {}
+unsafe impl<T: Send> Sync for HostMutex<T> {}
+
+/// Gives access to the value of a locked `HostMutex`, unlocking it when dropped.
+/// The host lock belongs to the thread that took it, so guards stay there:
+///
+/// ```compile_fail
+/// fn send<T: Send>() {}
+/// send::<ofx::HostMutexGuard<'static, u32>>();
+/// ```
+///
+/// and are only shared between threads when the value itself can be:
+///
+/// ```compile_fail
+/// fn sync<T: Sync>() {}
+/// sync::<ofx::HostMutexGuard<'static, std::cell::Cell<u32>>>();
+/// ```
+pub struct HostMutexGuard<'a, T> {
+	mutex: &'a HostMutex<T>,
+	// not Send, unlocking must happen on the locking thread
+	thread: PhantomData<*const ()>,
+}
+
+unsafe impl<'a, T: Sync> Sync for HostMutexGuard<'a, T> {}
+
+impl<T> HostMutex<T> {
+	pub(crate) fn new(multi_thread: OfxMultiThreadSuiteV1, value: T) -> Result<Self> {
+		let mut inner: OfxMutexHandle = std::ptr::null_mut();
+		suite_fn!(mutexCreate in multi_thread; &mut inner as *mut OfxMutexHandle, 0)?;
+		Ok(HostMutex {
+			multi_thread,
+			inner,
+			owner: AtomicUsize::new(0),
+			value: UnsafeCell::new(value),
+		})
+	}
+
+	// only this thread stores its own id, so a relaxed load sees it
+	fn check_not_held(&self) -> Result<()> {
+		if self.owner.load(Ordering::Relaxed) == current_thread() {
+			error!("Host mutex {:?} is already locked by this thread", self.inner);
+			Err(Error::InvalidValue)
+		} else {
+			Ok(())
+		}
+	}
+
+	fn guard(&self) -> HostMutexGuard<'_, T> {
+		self.owner.store(current_thread(), Ordering::Relaxed);
+		HostMutexGuard {
+			mutex: self,
+			thread: PhantomData,
+		}
+	}
+
+	pub fn lock(&self) -> Result<HostMutexGuard<'_, T>> {
+		self.check_not_held()?;
+		suite_fn!(mutexLock in self.multi_thread; self.inner)?;
+		Ok(self.guard())
+	}
+
+	/// Locks the mutex if no other thread holds it, without waiting
+	pub fn try_lock(&self) -> Result<Option<HostMutexGuard<'_, T>>> {
+		self.check_not_held()?;
+		match suite_call!(mutexTryLock in self.multi_thread; self.inner) {
+			ofx_sys::eOfxStatus_OK => Ok(Some(self.guard())),
+			ofx_sys::eOfxStatus_Failed => Ok(None),
+			other => Err(Error::from(other)),
+		}
+	}
+
+	pub fn get_mut(&mut self) -> &mut T {
+		unsafe { &mut *self.value.get() }
+	}
+}
+
+impl<T> Drop for HostMutex<T> {
+	fn drop(&mut self) {
+		let status = unsafe { self.multi_thread.mutexDestroy.map(|destroy| destroy(self.inner)) };
+		if status != Some(eOfxStatus_OK) {
+			error!("Unable to destroy host mutex {:?}", self.inner);
+		}
+	}
+}
+
+impl<'a, T> Deref for HostMutexGuard<'a, T> {
+	type Target = T;
+	fn deref(&self) -> &T {
+		unsafe { &*self.mutex.value.get() }
+	}
+}
+
+impl<'a, T> DerefMut for HostMutexGuard<'a, T> {
+	fn deref_mut(&mut self) -> &mut T {
+		unsafe { &mut *self.mutex.value.get() }
+	}
+}
+
+impl<'a, T> Drop for HostMutexGuard<'a, T> {
+	fn drop(&mut self) {
+		self.mutex.owner.store(0, Ordering::Relaxed);
+		let multi_thread = &self.mutex.multi_thread;
+		let status = unsafe { multi_thread.mutexUnLock.map(|unlock| unlock(self.mutex.inner)) };
+		if status != Some(eOfxStatus_OK) {
+			error!("Unable to unlock host mutex {:?}", self.mutex.inner);
+		}
+	}
+}
+
+// Per thread ranges of indices for work stealing, each packed as
+// `start << 32 | end` so that it can be updated with a single CAS. A thread
+// takes indices from the front of its own range; once that is empty it
+// steals the back half of the largest range left.
+struct WorkQueues {
+	ranges: Vec<AtomicU64>,
+}
+
+fn pack(start: usize, end: usize) -> u64 {
+	(start as u64) << 32 | end as u64
+}
+
+fn unpack(range: u64) -> (usize, usize) {
+	((range >> 32) as usize, (range & 0xffff_ffff) as usize)
+}
+
+impl WorkQueues {
+	// indices are stored as u32, `range.end` must fit
+	fn new(range: Range<usize>, threads: usize) -> Self {
+		let threads = threads.max(1);
+		let len = range.end.saturating_sub(range.start);
+		let ranges = (0..threads)
+			.map(|thread| {
+				let start = range.start + len * thread / threads;
+				let end = range.start + len * (thread + 1) / threads;
+				AtomicU64::new(pack(start, end))
+			})
+			.collect();
+		WorkQueues { ranges }
+	}
+
+	fn pop(&self, thread: usize) -> Option<usize> {
+		let own = &self.ranges[thread];
+		let mut current = own.load(Ordering::Acquire);
+		loop {
+			let (start, end) = unpack(current);
+			if start >= end {
+				return None;
+			}
+			match own.compare_exchange_weak(
+				current,
+				pack(start + 1, end),
+				Ordering::AcqRel,
+				Ordering::Acquire,
+			) {
+				Ok(_) => return Some(start),
+				Err(actual) => current = actual,
+			}
+		}
+	}
+
+	fn steal(&self, thread: usize) -> Option<usize> {
+		loop {
+			let victim = (0..self.ranges.len())
+				.filter(|&other| other != thread)
+				.map(|other| (other, self.ranges[other].load(Ordering::Acquire)))
+				.max_by_key(|&(_, range)| {
+					let (start, end) = unpack(range);
+					end.saturating_sub(start)
+				});
+			let (victim, current) = victim?;
+			let (start, end) = unpack(current);
+			if start >= end {
+				return None;
+			}
+			let middle = start + (end - start) / 2;
+			if self.ranges[victim]
+				.compare_exchange(
+					current,
+					pack(start, middle),
+					Ordering::AcqRel,
+					Ordering::Acquire,
+				)
+				.is_ok()
+			{
+				// nobody steals from an empty range, so a plain store is safe
+				self.ranges[thread].store(pack(middle + 1, end), Ordering::Release);
+				return Some(middle);
+			}
+		}
+	}
+
+	fn next(&self, thread: usize) -> Option<usize> {
+		self.pop(thread).or_else(|| self.steal(thread))
+	}
+}
+
+// Shared state of a parallel_for call, owned by the calling thread for the
+// duration of the blocking multiThread call.
+struct ParallelFor<'a, F> {
+	body: &'a F,
+	abort: Option<AbortPoll>,
+	queues: WorkQueues,
+	failed: AtomicBool,
+	error: Mutex<Option<Error>>,
+	panic: Mutex<Option<Box<dyn Any + Send>>>,
+	scope: Option<LogScope>,
+}
+
+impl<'a, F> ParallelFor<'a, F>
+where
+	F: Fn(usize) -> Result<()> + Sync,
+{
+	fn new(body: &'a F, abort: Option<AbortPoll>, range: Range<usize>, threads: usize) -> Self {
+		ParallelFor {
+			body,
+			abort,
+			queues: WorkQueues::new(range, threads),
+			failed: AtomicBool::new(false),
+			error: Mutex::new(None),
+			panic: Mutex::new(None),
+			scope: LogScope::current(),
+		}
+	}
+
+	fn work(&self, thread: usize) {
+		let thread = thread % self.queues.ranges.len();
+		while !self.failed.load(Ordering::Relaxed) {
+			let index = match self.queues.next(thread) {
+				Some(index) => index,
+				None => return,
+			};
+			let (body, abort) = (self.body, self.abort.as_ref());
+			// polled before every item, so an abort stops the loop within one item
+			let item = || abort.map_or(Ok(()), AbortPoll::check).and_then(|_| body(index));
+			match panic::catch_unwind(AssertUnwindSafe(item)) {
+				Ok(Ok(())) => {}
+				Ok(Err(error)) => {
+					self.fail();
+					let mut first = self.error.lock().unwrap_or_else(|e| e.into_inner());
+					first.get_or_insert(error);
+					return;
+				}
+				Err(payload) => {
+					self.fail();
+					let mut first = self.panic.lock().unwrap_or_else(|e| e.into_inner());
+					first.get_or_insert(payload);
+					return;
+				}
+			}
+		}
+	}
+
+	fn fail(&self) {
+		self.failed.store(true, Ordering::Relaxed);
+	}
+
+	unsafe extern "C" fn run(thread_index: UnsignedInt, _thread_max: UnsignedInt, me: VoidPtrMut) {
+		let state = &*(me as *const Self);
+		let _scope = state.scope.clone().map(LogScope::enter);
+		state.work(thread_index as usize)
+	}
+
+	fn finish(self) -> Result<()> {
+		if let Some(payload) = self.panic.into_inner().unwrap_or_else(|e| e.into_inner()) {
+			panic::resume_unwind(payload);
+		}
+		match self.error.into_inner().unwrap_or_else(|e| e.into_inner()) {
+			Some(error) => Err(error),
+			None => Ok(()),
+		}
+	}
+}
+
+pub(crate) fn parallel_for<F>(
+	multi_thread: &OfxMultiThreadSuiteV1,
+	abort: Option<AbortPoll>,
+	range: Range<usize>,
+	body: &F,
+) -> Result<()>
+where
+	F: Fn(usize) -> Result<()> + Sync,
+{
+	if range.start >= range.end {
+		return Ok(());
+	}
+	if range.end > u32::MAX as usize {
+		return Err(Error::InvalidValue);
+	}
+	let mut threads: UnsignedInt = 1;
+	suite_fn!(multiThreadNumCPUs in multi_thread; &mut threads as *mut UnsignedInt)?;
+	let threads = threads.min((range.end - range.start) as UnsignedInt).max(1);
+	let state = ParallelFor::new(body, abort, range, threads as usize);
+	if threads == 1 {
+		state.work(0);
+	} else {
+		suite_fn!(multiThread in multi_thread;
+			Some(ParallelFor::<F>::run), threads, &state as *const ParallelFor<F> as VoidPtrMut)?;
+	}
+	state.finish()
+}
+
+// Lets worker threads write to disjoint rows of one image
+struct RowPointers<'a, T>
+where
+	T: PixelFormat,
+{
+	image: &'a ImageDescriptorMut<'a, T>,
+}
+
+unsafe impl<'a, T> Sync for RowPointers<'a, T> where T: PixelFormat + Send {}
+
+pub(crate) fn parallel_rows<T, F>(
+	multi_thread: &OfxMultiThreadSuiteV1,
+	abort: Option<AbortPoll>,
+	image: &mut ImageDescriptorMut<T>,
+	body: &F,
+) -> Result<()>
+where
+	T: PixelFormat + Send,
+	F: Fn(Int, &mut [T]) -> Result<()> + Sync,
+{
+	let bounds = image.bounds();
+	let width = (bounds.x2 - bounds.x1).max(0) as usize;
+	// hosts may give zero width images a null data pointer
+	if width == 0 {
+		return Ok(());
+	}
+	let rows = RowPointers { image: &*image };
+	let rows = &rows;
+	parallel_for(
+		multi_thread,
+		abort,
+		0..(bounds.y2 - bounds.y1).max(0) as usize,
+		&|index| {
+			let y = bounds.y1 + index as Int;
+			// each index is handed out exactly once, so no two threads share a row
+			let row = unsafe { std::slice::from_raw_parts_mut(rows.image.row_ptr(y), width) };
+			body(y, row)
+		},
+	)
+}
+
+// Lets worker threads write to disjoint tiles of one image
+struct TilePointers<'a, T>
+where
+	T: PixelFormat,
+{
+	image: &'a ImageDescriptorMut<'a, T>,
+}
+
+unsafe impl<'a, T> Sync for TilePointers<'a, T> where T: PixelFormat + Send {}
+
+pub(crate) fn parallel_tiles<T, F>(
+	multi_thread: &OfxMultiThreadSuiteV1,
+	abort: Option<AbortPoll>,
+	image: &mut ImageDescriptorMut<T>,
+	grid: &TileGrid,
+	body: &F,
+) -> Result<()>
+where
+	T: PixelFormat + Send,
+	F: Fn(RectI, ImageBufferMut<T>) -> Result<()> + Sync,
+{
+	let tiles = TilePointers { image: &*image };
+	let tiles = &tiles;
+	parallel_for(multi_thread, abort, 0..grid.len(), &|index| {
+		let tile = grid.tile(index);
+		// tiles of a grid are disjoint and each index is handed out once
+		body(tile, unsafe { tiles.image.tile_unchecked(tile) })
+	})
+}
+
+mod tests {
+	use super::*;
+
+	// a recursive mutex that never blocks, as seen from a single thread
+	unsafe extern "C" fn create(mutex: *mut OfxMutexHandle, _: std::os::raw::c_int) -> OfxStatus {
+		*mutex = std::ptr::NonNull::<u8>::dangling().as_ptr() as OfxMutexHandle;
+		eOfxStatus_OK
+	}
+
+	unsafe extern "C" fn succeed(_: OfxMutexHandle) -> OfxStatus {
+		eOfxStatus_OK
+	}
+
+	fn multi_thread() -> OfxMultiThreadSuiteV1 {
+		OfxMultiThreadSuiteV1 {
+			multiThread: None,
+			multiThreadNumCPUs: None,
+			multiThreadIndex: None,
+			multiThreadIsSpawnedThread: None,
+			mutexCreate: Some(create),
+			mutexDestroy: Some(succeed),
+			mutexLock: Some(succeed),
+			mutexUnLock: Some(succeed),
+			mutexTryLock: Some(succeed),
+		}
+	}
+
+	#[test]
+	fn relocking_on_one_thread_fails() {
+		let mutex = HostMutex::new(multi_thread(), 1).unwrap();
+		{
+			let mut guard = mutex.lock().unwrap();
+			*guard += 1;
+			assert!(mutex.lock().is_err());
+			assert!(mutex.try_lock().is_err());
+		}
+		assert_eq!(*mutex.try_lock().unwrap().unwrap(), 2);
+		assert_eq!(*mutex.lock().unwrap(), 2);
+	}
+
+	#[test]
+	fn guards_share_sync_values() {
+		fn sync<T: Sync>() {}
+		sync::<HostMutexGuard<'static, u32>>();
+	}
+
+	#[test]
+	fn chunks_cover_range_once() {
+		let queues = WorkQueues::new(3..103, 4);
+		let mut seen = vec![0; 103];
+		// thread 0 works alone, taking its own share and stealing the rest
+		while let Some(index) = queues.next(0) {
+			seen[index] += 1;
+		}
+		assert!(seen[..3].iter().all(|&count| count == 0));
+		assert!(seen[3..].iter().all(|&count| count == 1));
+	}
+
+	#[test]
+	fn idle_threads_steal_half() {
+		let queues = WorkQueues::new(0..8, 2);
+		assert_eq!(queues.pop(1), Some(4));
+		assert_eq!(queues.pop(1), Some(5));
+		// thread 0 empties its range, then takes the back half of thread 1's
+		for index in 0..4 {
+			assert_eq!(queues.next(0), Some(index));
+		}
+		assert_eq!(queues.next(0), Some(7));
+		assert_eq!(queues.next(1), Some(6));
+		assert_eq!(queues.next(0), None);
+		assert_eq!(queues.next(1), None);
+	}
+
+	#[test]
+	fn threads_share_uneven_work() {
+		let seen: Vec<AtomicUsize> = (0..500).map(|_| AtomicUsize::new(0)).collect();
+		let body = |index: usize| {
+			// a few expensive items at the start
+			if index < 4 {
+				std::thread::sleep(std::time::Duration::from_millis(20));
+			}
+			seen[index].fetch_add(1, Ordering::Relaxed);
+			Ok(())
+		};
+		let state = ParallelFor::new(&body, None, 0..500, 4);
+		std::thread::scope(|scope| {
+			for thread in 0..4 {
+				let state = &state;
+				scope.spawn(move || state.work(thread));
+			}
+		});
+		assert!(state.finish().is_ok());
+		assert!(seen.iter().all(|count| count.load(Ordering::Relaxed) == 1));
+	}
+
+	#[test]
+	fn first_error_stops_the_loop() {
+		let visited = AtomicUsize::new(0);
+		let body = |index: usize| {
+			visited.fetch_add(1, Ordering::Relaxed);
+			if index == 10 {
+				Err(Error::InvalidValue)
+			} else {
+				Ok(())
+			}
+		};
+		let state = ParallelFor::new(&body, None, 0..1000, 1);
+		state.work(0);
+		assert!(state.finish().is_err());
+		assert_eq!(visited.load(Ordering::Relaxed), 11);
+	}
+
+	unsafe extern "C" fn aborted(_: OfxImageEffectHandle) -> std::os::raw::c_int {
+		1
+	}
+
+	#[test]
+	fn aborted_renders_stop() {
+		let visited = AtomicUsize::new(0);
+		let body = |_: usize| {
+			visited.fetch_add(1, Ordering::Relaxed);
+			Ok(())
+		};
+		let abort = AbortPoll::new(aborted, std::ptr::null_mut());
+		let state = ParallelFor::new(&body, Some(abort), 0..10, 1);
+		state.work(0);
+		assert!(matches!(state.finish(), Err(Error::Aborted)));
+		assert_eq!(visited.load(Ordering::Relaxed), 0);
+	}
+
+	#[test]
+	#[should_panic(expected = "boom")]
+	fn panics_are_resumed() {
+		let body = |_: usize| -> Result<()> { panic!("boom") };
+		let state = ParallelFor::new(&body, None, 0..10, 1);
+		state.work(0);
+		state.finish().ok();
+	}
+}
@@ -0,0 +1,163 @@
+use types::*;
+
+/// The order tiles of a `TileGrid` are handed out in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileOrder {
+	RowMajor,
+	/// Morton order, keeping consecutive tiles close in both directions
+	ZOrder,
+}
+
+/// Splits a window of an image into tiles of up to `tile_width` by
+/// `tile_height` pixels. Edge tiles are clipped to the window.
+#[derive(Debug, Clone)]
+pub struct TileGrid {
+	window: RectI,
+	tile_width: Int,
+	tile_height: Int,
+	columns: usize,
+	rows: usize,
+	order: Vec<(u32, u32)>,
+}
+
+pub(crate) fn intersect(a: RectI, b: RectI) -> RectI {
+	let x1 = a.x1.max(b.x1);
+	let y1 = a.y1.max(b.y1);
+	RectI {
+		x1,
+		y1,
+		x2: a.x2.min(b.x2).max(x1),
+		y2: a.y2.min(b.y2).max(y1),
+	}
+}
+
+// interleaves the bits of x and y, x in the even bits
+fn morton(x: u32, y: u32) -> u64 {
+	fn spread(v: u32) -> u64 {
+		let mut v = u64::from(v);
+		v = (v | (v << 16)) & 0x0000_ffff_0000_ffff;
+		v = (v | (v << 8)) & 0x00ff_00ff_00ff_00ff;
+		v = (v | (v << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+		v = (v | (v << 2)) & 0x3333_3333_3333_3333;
+		v = (v | (v << 1)) & 0x5555_5555_5555_5555;
+		v
+	}
+	spread(x) | (spread(y) << 1)
+}
+
+impl TileGrid {
+	/// 64 by 64 tiles of `render_window` in Z order, clipped to `bounds`,
+	/// the bounds of the image written to
+	pub fn new(render_window: RectI, bounds: RectI) -> Self {
+		TileGrid {
+			window: intersect(render_window, bounds),
+			tile_width: 64,
+			tile_height: 64,
+			columns: 0,
+			rows: 0,
+			order: Vec::new(),
+		}
+		.tile_size(64, 64)
+	}
+
+	pub fn tile_size(self, tile_width: Int, tile_height: Int) -> Self {
+		let tile_width = tile_width.max(1);
+		let tile_height = tile_height.max(1);
+		let width = (self.window.x2 - self.window.x1) as usize;
+		let height = (self.window.y2 - self.window.y1) as usize;
+		let columns = width.div_ceil(tile_width as usize);
+		let rows = height.div_ceil(tile_height as usize);
+		TileGrid {
+			tile_width,
+			tile_height,
+			columns,
+			rows,
+			..self
+		}
+		.order(TileOrder::ZOrder)
+	}
+
+	pub fn order(mut self, order: TileOrder) -> Self {
+		let mut tiles: Vec<(u32, u32)> = (0..self.rows as u32)
+			.flat_map(|row| (0..self.columns as u32).map(move |column| (column, row)))
+			.collect();
+		if order == TileOrder::ZOrder {
+			tiles.sort_by_key(|&(column, row)| morton(column, row));
+		}
+		self.order = tiles;
+		self
+	}
+
+	/// The render window clipped to the image
+	pub fn window(&self) -> RectI {
+		self.window
+	}
+
+	pub fn len(&self) -> usize {
+		self.order.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.order.is_empty()
+	}
+
+	/// The `index`th tile in the grid's order
+	pub fn tile(&self, index: usize) -> RectI {
+		let (column, row) = self.order[index];
+		let x1 = self.window.x1 + column as Int * self.tile_width;
+		let y1 = self.window.y1 + row as Int * self.tile_height;
+		RectI {
+			x1,
+			y1,
+			x2: (x1 + self.tile_width).min(self.window.x2),
+			y2: (y1 + self.tile_height).min(self.window.y2),
+		}
+	}
+
+	pub fn tiles(&self) -> impl Iterator<Item = RectI> + '_ {
+		(0..self.len()).map(move |index| self.tile(index))
+	}
+}
+
+mod tests {
+	use super::*;
+
+	fn rect(x1: Int, y1: Int, x2: Int, y2: Int) -> RectI {
+		RectI { x1, y1, x2, y2 }
+	}
+
+	#[test]
+	fn tiles_cover_clipped_window_once() {
+		let bounds = rect(-10, 0, 100, 50);
+		for order in &[TileOrder::RowMajor, TileOrder::ZOrder] {
+			let grid = TileGrid::new(rect(-20, 5, 90, 60), bounds)
+				.tile_size(16, 8)
+				.order(*order);
+			let window = grid.window();
+			assert_eq!((window.x1, window.y1, window.x2, window.y2), (-10, 5, 90, 50));
+			let mut covered = vec![0; 100 * 45];
+			for tile in grid.tiles() {
+				assert!(tile.x1 < tile.x2 && tile.y1 < tile.y2);
+				for y in tile.y1..tile.y2 {
+					for x in tile.x1..tile.x2 {
+						covered[((y - 5) * 100 + x + 10) as usize] += 1;
+					}
+				}
+			}
+			assert!(covered.iter().all(|&count| count == 1));
+		}
+	}
+
+	#[test]
+	fn z_order() {
+		let grid = TileGrid::new(rect(0, 0, 4, 4), rect(0, 0, 4, 4)).tile_size(1, 1);
+		let corners: Vec<(Int, Int)> = grid.tiles().take(5).map(|tile| (tile.x1, tile.y1)).collect();
+		assert_eq!(corners, vec![(0, 0), (1, 0), (0, 1), (1, 1), (2, 0)]);
+	}
+
+	#[test]
+	fn empty_window() {
+		let grid = TileGrid::new(rect(200, 0, 300, 10), rect(0, 0, 100, 100));
+		assert!(grid.is_empty());
+	}
+}
@@ -39,6 +39,99 @@ pub type YUVAColourB = ofx_sys::OfxYUVAColourB;
 pub type YUVAColourS = ofx_sys::OfxYUVAColourS;
 pub type YUVAColourF = ofx_sys::OfxYUVAColourF;
 
+/// A 16 bit IEEE 754 float, the channel type of `BitDepth::Half` images
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Half(pub u16);
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RGBAColourH {
+	pub r: Half,
+	pub g: Half,
+	pub b: Half,
+	pub a: Half,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RGBColourH {
+	pub r: Half,
+	pub g: Half,
+	pub b: Half,
+}
+
+impl Half {
+	/// Rounds to the nearest half, ties to even; out of range values become infinite
+	pub fn from_f32(value: f32) -> Half {
+		let bits = value.to_bits();
+		let sign = ((bits >> 16) & 0x8000) as u16;
+		let exponent = ((bits >> 23) & 0xff) as i32;
+		let mantissa = bits & 0x007f_ffff;
+		if exponent == 0xff {
+			let nan = if mantissa != 0 { 0x0200 } else { 0 };
+			return Half(sign | 0x7c00 | nan);
+		}
+		let exponent = exponent - 127 + 15;
+		if exponent >= 0x1f {
+			return Half(sign | 0x7c00);
+		}
+		// the bit above the round bit takes part so that ties go to even
+		let round = |value: u32, shifted: u32, shift: u32| {
+			let round_bit = 1 << (shift - 1);
+			if value & round_bit != 0 && value & (3 * round_bit - 1) != 0 {
+				shifted + 1
+			} else {
+				shifted
+			}
+		};
+		if exponent <= 0 {
+			// below 2^-25, half the smallest subnormal, everything rounds to zero;
+			// from there up the shift is at most 24 and the round bit is the
+			// implicit one, so 2^-25 itself ties to zero and anything above it
+			// rounds up to 0x0001
+			if exponent < -10 {
+				return Half(sign);
+			}
+			let mantissa = mantissa | 0x0080_0000;
+			let shift = (14 - exponent) as u32;
+			return Half(sign | round(mantissa, mantissa >> shift, shift) as u16);
+		}
+		let half = ((exponent as u32) << 10) | (mantissa >> 13);
+		// a carry out of the mantissa correctly bumps the exponent
+		Half(sign | round(mantissa, half, 13) as u16)
+	}
+
+	pub fn to_f32(self) -> f32 {
+		let sign = u32::from(self.0 & 0x8000) << 16;
+		let exponent = u32::from((self.0 >> 10) & 0x1f);
+		let mantissa = u32::from(self.0 & 0x03ff);
+		let bits = match exponent {
+			0 if mantissa == 0 => sign,
+			0 => {
+				let shift = mantissa.leading_zeros() - 21;
+				let mantissa = (mantissa << shift) & 0x03ff;
+				sign | ((127 - 15 + 1 - shift) << 23) | (mantissa << 13)
+			}
+			0x1f => sign | 0x7f80_0000 | (mantissa << 13),
+			_ => sign | ((exponent + 127 - 15) << 23) | (mantissa << 13),
+		};
+		f32::from_bits(bits)
+	}
+}
+
+impl From<f32> for Half {
+	fn from(value: f32) -> Half {
+		Half::from_f32(value)
+	}
+}
+
+impl From<Half> for f32 {
+	fn from(value: Half) -> f32 {
+		value.to_f32()
+	}
+}
+
 pub(crate) type SetHost = unsafe extern "C" fn(*mut ofx_sys::OfxHost);
 pub(crate) type MainEntry = unsafe extern "C" fn(
 	*const i8,
@@ -46,3 +139,37 @@ pub(crate) type MainEntry = unsafe extern "C" fn(
 	*mut ofx_sys::OfxPropertySetStruct,
 	*mut ofx_sys::OfxPropertySetStruct,
 ) -> Int;
+
+mod tests {
+	use super::*;
+
+	#[test]
+	fn half_round_trip() {
+		for value in &[0.0f32, -0.0, 1.0, -2.5, 0.333_251_95, 65504.0, 6.103_515_6e-5, 5.960_464_5e-8] {
+			assert_eq!(Half::from_f32(*value).to_f32(), *value);
+		}
+		assert_eq!(Half::from_f32(1.0), Half(0x3c00));
+		assert_eq!(Half::from_f32(65520.0), Half(0x7c00));
+		assert_eq!(Half::from_f32(1.0e-8), Half(0));
+		assert!(Half::from_f32(std::f32::NAN).to_f32().is_nan());
+		assert_eq!(Half::from_f32(std::f32::NEG_INFINITY).to_f32(), std::f32::NEG_INFINITY);
+	}
+
+	#[test]
+	fn half_ties_to_even() {
+		// 1 + 2^-11 is halfway between 1 and the next half
+		assert_eq!(Half::from_f32(1.000_488_3), Half(0x3c00));
+		assert_eq!(Half::from_f32(1.001_464_8), Half(0x3c02));
+		assert_eq!(Half::from_f32(1.000_488_4), Half(0x3c01));
+	}
+
+	#[test]
+	fn half_smallest_subnormal() {
+		let smallest = 2.0f32.powi(-24);
+		assert_eq!(Half::from_f32(smallest * 0.75), Half(0x0001));
+		assert_eq!(Half::from_f32(-smallest * 0.75), Half(0x8001));
+		assert_eq!(Half::from_f32(smallest * 0.5), Half(0));
+		assert_eq!(Half::from_f32(smallest * 0.499), Half(0));
+		assert_eq!(Half::from_f32(smallest * 1.5), Half(0x0002));
+	}
+}
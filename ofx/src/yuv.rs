@@ -0,0 +1,213 @@
+use image::*;
+
+/// The luma coefficients of a video standard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YuvMatrix {
+	Rec601,
+	Rec709,
+	Rec2020,
+}
+
+impl YuvMatrix {
+	// (Kr, Kb), Kg is what is left
+	fn coefficients(self) -> (f32, f32) {
+		match self {
+			YuvMatrix::Rec601 => (0.299, 0.114),
+			YuvMatrix::Rec709 => (0.2126, 0.0722),
+			YuvMatrix::Rec2020 => (0.2627, 0.0593),
+		}
+	}
+}
+
+/// How Y'CbCr values map onto the channel range. Legal (studio) range keeps
+/// the 16-235 luma and 16-240 chroma codes of 8 bits, scaled up for deeper
+/// codes, with chroma centred on code 128 (512 at 10 bits).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YuvRange {
+	Full,
+	Legal,
+}
+
+impl YuvRange {
+	// (luma offset, luma scale, chroma offset, chroma scale) for codes of `bits`
+	fn scale(self, bits: u32) -> (f32, f32, f32, f32) {
+		match self {
+			YuvRange::Full => (0.0, 1.0, 0.5, 1.0),
+			YuvRange::Legal => {
+				let max = ((1u64 << bits) - 1) as f32;
+				let step = (1u64 << (bits - 8)) as f32;
+				let centre = (1u64 << (bits - 1)) as f32;
+				(16.0 * step / max, 219.0 * step / max, centre / max, 224.0 * step / max)
+			}
+		}
+	}
+}
+
+/// Converts between RGB and YUV with normalised channels, as in
+/// `ImageComponent::YUVA` images. Full range chroma is centred on 0.5.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct YuvConversion {
+	kr: f32,
+	kb: f32,
+	luma_offset: f32,
+	luma_scale: f32,
+	chroma_offset: f32,
+	chroma_scale: f32,
+}
+
+impl YuvConversion {
+	/// Legal range values are those of 8 bit codes, see `with_bits`
+	pub fn new(matrix: YuvMatrix, range: YuvRange) -> Self {
+		YuvConversion::with_bits(matrix, range, 8)
+	}
+
+	/// Legal range values are those of `bits` codes, from 8 to 16, normalised
+	/// to the channel range; full range does not depend on `bits`
+	pub fn with_bits(matrix: YuvMatrix, range: YuvRange, bits: u32) -> Self {
+		let (kr, kb) = matrix.coefficients();
+		let (luma_offset, luma_scale, chroma_offset, chroma_scale) = range.scale(bits.clamp(8, 16));
+		YuvConversion {
+			kr,
+			kb,
+			luma_offset,
+			luma_scale,
+			chroma_offset,
+			chroma_scale,
+		}
+	}
+
+	pub fn to_rgb(&self, y: f32, u: f32, v: f32) -> (f32, f32, f32) {
+		let y = (y - self.luma_offset) / self.luma_scale;
+		let u = (u - self.chroma_offset) / self.chroma_scale;
+		let v = (v - self.chroma_offset) / self.chroma_scale;
+		let r = y + 2.0 * (1.0 - self.kr) * v;
+		let b = y + 2.0 * (1.0 - self.kb) * u;
+		let g = (y - self.kr * r - self.kb * b) / (1.0 - self.kr - self.kb);
+		(r, g, b)
+	}
+
+	pub fn from_rgb(&self, r: f32, g: f32, b: f32) -> (f32, f32, f32) {
+		let y = self.kr * r + (1.0 - self.kr - self.kb) * g + self.kb * b;
+		let u = (b - y) / (2.0 * (1.0 - self.kb));
+		let v = (r - y) / (2.0 * (1.0 - self.kr));
+		(
+			self.luma_offset + y * self.luma_scale,
+			self.chroma_offset + u * self.chroma_scale,
+			self.chroma_offset + v * self.chroma_scale,
+		)
+	}
+
+	pub fn to_rgba<S, D>(&self, src: &S) -> D
+	where
+		S: PixelFormatYUVA,
+		D: PixelFormatRGBA,
+	{
+		let (r, g, b) = self.to_rgb(src.y().to_f32(), src.u().to_f32(), src.v().to_f32());
+		D::new(
+			D::ChannelValue::from_f32(r),
+			D::ChannelValue::from_f32(g),
+			D::ChannelValue::from_f32(b),
+			D::ChannelValue::from_f32(src.a().to_f32()),
+		)
+	}
+
+	pub fn to_yuva<S, D>(&self, src: &S) -> D
+	where
+		S: PixelFormatRGBA,
+		D: PixelFormatYUVA,
+	{
+		let (y, u, v) = self.from_rgb(src.r().to_f32(), src.g().to_f32(), src.b().to_f32());
+		D::new(
+			D::ChannelValue::from_f32(y),
+			D::ChannelValue::from_f32(u),
+			D::ChannelValue::from_f32(v),
+			D::ChannelValue::from_f32(src.a().to_f32()),
+		)
+	}
+
+	pub fn row_to_rgba<S, D>(&self, src: &[S], dst: &mut [D])
+	where
+		S: PixelFormatYUVA,
+		D: PixelFormatRGBA,
+	{
+		for (dst, src) in dst.iter_mut().zip(src) {
+			*dst = self.to_rgba(src);
+		}
+	}
+
+	pub fn row_to_yuva<S, D>(&self, src: &[S], dst: &mut [D])
+	where
+		S: PixelFormatRGBA,
+		D: PixelFormatYUVA,
+	{
+		for (dst, src) in dst.iter_mut().zip(src) {
+			*dst = self.to_yuva(src);
+		}
+	}
+}
+
+mod tests {
+	use super::*;
+	use enums::*;
+	use types::*;
+
+	fn assert_close(a: (f32, f32, f32), b: (f32, f32, f32)) {
+		assert!(
+			(a.0 - b.0).abs() < 1e-5 && (a.1 - b.1).abs() < 1e-5 && (a.2 - b.2).abs() < 1e-5,
+			"{:?} != {:?}",
+			a,
+			b
+		);
+	}
+
+	#[test]
+	fn round_trip() {
+		for matrix in &[YuvMatrix::Rec601, YuvMatrix::Rec709, YuvMatrix::Rec2020] {
+			for range in &[YuvRange::Full, YuvRange::Legal] {
+				let conversion = YuvConversion::new(*matrix, *range);
+				for rgb in &[(0.0, 0.0, 0.0), (1.0, 1.0, 1.0), (0.8, 0.1, 0.3), (0.0, 0.0, 1.0)] {
+					let (y, u, v) = conversion.from_rgb(rgb.0, rgb.1, rgb.2);
+					assert_close(conversion.to_rgb(y, u, v), *rgb);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn white_and_black() {
+		let full = YuvConversion::new(YuvMatrix::Rec709, YuvRange::Full);
+		assert_close(full.from_rgb(1.0, 1.0, 1.0), (1.0, 0.5, 0.5));
+		let legal = YuvConversion::new(YuvMatrix::Rec709, YuvRange::Legal);
+		let grey = 128.0 / 255.0;
+		assert_close(legal.from_rgb(1.0, 1.0, 1.0), (235.0 / 255.0, grey, grey));
+		assert_close(legal.from_rgb(0.0, 0.0, 0.0), (16.0 / 255.0, grey, grey));
+		// pure blue sits at the top of the chroma range
+		let (y, u, v) = legal.from_rgb(0.0, 0.0, 1.0);
+		assert_close((y, u, v), (16.0 / 255.0 + 0.0722 * 219.0 / 255.0, 240.0 / 255.0, v));
+	}
+
+	#[test]
+	fn legal_ten_bit_codes() {
+		let legal = YuvConversion::with_bits(YuvMatrix::Rec709, YuvRange::Legal, 10);
+		let grey = 512.0 / 1023.0;
+		assert_close(legal.from_rgb(0.0, 0.0, 0.0), (64.0 / 1023.0, grey, grey));
+		assert_close(legal.from_rgb(1.0, 1.0, 1.0), (940.0 / 1023.0, grey, grey));
+		// pure red sits at the top of the chroma range
+		let (_, _, v) = legal.from_rgb(1.0, 0.0, 0.0);
+		assert!((v - 960.0 / 1023.0).abs() < 1e-5, "{}", v);
+	}
+
+	#[test]
+	fn pixels() {
+		let conversion = YuvConversion::new(YuvMatrix::Rec601, YuvRange::Full);
+		let yuva = YUVAColourF {
+			y: 1.0,
+			u: 0.5,
+			v: 0.5,
+			a: 0.25,
+		};
+		let rgba: RGBAColourB = conversion.to_rgba(&yuva);
+		assert_eq!((rgba.r, rgba.g, rgba.b, rgba.a), (255, 255, 255, 63));
+		assert_eq!(YUVAColourF::components(), ImageComponent::YUVA);
+	}
+}